@@ -0,0 +1,134 @@
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use std::{
+    process::{Command, Stdio},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::host::config::{EscalationTool, HostConfiguration};
+
+use super::SystemAction;
+
+/// Delay between two refreshes of the cached credentials
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Refresh the credentials of the escalation tool until dropped
+pub struct KeepAlive {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        // Closing the channel wakes the thread up
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Command checking the credentials, prompting for them when asked
+fn credentials_command(tool: EscalationTool, prompt: bool) -> Option<Command> {
+    let mut command = Command::new(tool.program());
+    match (tool, prompt) {
+        (EscalationTool::Sudo, true) => command.arg("-v"),
+        (EscalationTool::Sudo, false) => command.args(["-n", "-v"]),
+        (EscalationTool::Doas, true) => command.arg("true"),
+        (EscalationTool::Doas, false) => command.args(["-n", "true"]),
+        (EscalationTool::Run0, true) => command.arg("true"),
+        // polkit keeps the authorization on its own
+        (EscalationTool::Run0, false) => return None,
+    };
+    return Some(command);
+}
+
+pub fn needs_escalation(sytem_actions: &Vec<SystemAction>, config: &HostConfiguration) -> bool {
+    return sytem_actions.iter().any(|sysaction| match sysaction {
+        SystemAction::Package { source, .. } => config
+            .package_managers
+            .get(source)
+            .is_some_and(|pm| pm.escalate),
+        SystemAction::Script { escalate, .. } => *escalate,
+        SystemAction::File { escalate, .. } => *escalate,
+    });
+}
+
+/// dotstrap has to run as the user owning the dotfiles, or ~ is the home of root
+pub fn check_not_escalated() -> Result<()> {
+    for variable in ["SUDO_USER", "DOAS_USER", "RUN0_USER"] {
+        if let Ok(user) = std::env::var(variable) {
+            bail!(
+                "dotstrap is running as root instead of {}, run it as {} and use become: true where root is needed",
+                user,
+                user
+            );
+        }
+    }
+    return Ok(());
+}
+
+/// Ask for the credentials once, then keep them cached while the actions run
+pub fn escalate(tool: EscalationTool) -> Result<KeepAlive> {
+    println!(
+        "[{}] {} is needed for some actions",
+        "BECOME".yellow(),
+        tool.program()
+    );
+    let status = credentials_command(tool, true)
+        .unwrap()
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .context(format!("\"{}\" failed to start", tool.program()))?;
+    if !status.success() {
+        bail!("Unable to get the credentials from {}", tool.program());
+    }
+    if credentials_command(tool, false).is_none() {
+        return Ok(KeepAlive {
+            stop: None,
+            thread: None,
+        });
+    }
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            let _ = credentials_command(tool, false)
+                .unwrap()
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+    });
+    return Ok(KeepAlive {
+        stop: Some(stop),
+        thread: Some(thread),
+    });
+}
+
+/// Run a command with the escalation tool, keeping its environment
+pub fn escalate_command(command: &Command, tool: EscalationTool) -> Command {
+    let mut escalated = Command::new(tool.program());
+    // The escalation tools reset the environment, env sets it back
+    let envs: Vec<String> = command
+        .get_envs()
+        .filter_map(|(name, value)| {
+            Some(format!(
+                "{}={}",
+                name.to_string_lossy(),
+                value?.to_string_lossy()
+            ))
+        })
+        .collect();
+    if !envs.is_empty() {
+        escalated.arg("env").args(envs);
+    }
+    escalated
+        .arg(command.get_program())
+        .args(command.get_args());
+    return escalated;
+}
@@ -1,16 +1,204 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use colored::Colorize;
 use pathbuf::pathbuf;
 use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fmt::{self, Display},
+    io::{BufRead, BufReader, Read},
     os::unix::prelude::PermissionsExt,
-    process::{Command, Output},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
 };
 
-use crate::host::config::HostConfiguration;
+use crate::{
+    backup::BackupStore,
+    helpers::checksum_file,
+    host::config::{EscalationTool, HostConfiguration},
+    variables::VariableContext,
+};
+
+use super::{
+    escalation::escalate_command,
+    scheduler::{action_package_path, Scheduler},
+    template::render_file,
+    FileOperation, PackageOperation, ScriptOperation, SystemAction,
+};
+
+#[derive(Debug)]
+pub enum ExecutionError {
+    /// The command could not be started
+    Spawn {
+        command: String,
+        error: std::io::Error,
+    },
+    /// The command exited with a non-zero status
+    Status { command: String, code: Option<i32> },
+    /// Any other failure, like a file operation
+    Other(anyhow::Error),
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::Spawn { command, error } => {
+                write!(f, "\"{}\" failed to start: {}", command, error)
+            }
+            ExecutionError::Status { command, code, .. } => match code {
+                Some(code) => write!(f, "\"{}\" exited with status {}", command, code),
+                None => write!(f, "\"{}\" was killed by a signal", command),
+            },
+            ExecutionError::Other(error) => write!(f, "{:#}", error),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+impl From<anyhow::Error> for ExecutionError {
+    fn from(error: anyhow::Error) -> Self {
+        return ExecutionError::Other(error);
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Succeeded,
+    Failed(ExecutionError),
+    /// Not run because a previous action failed
+    Skipped,
+}
+
+/// How the output of the commands is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Printed while the command runs, prefixed by the action tag
+    Stream,
+    /// Printed only when the command fails
+    Quiet,
+}
+
+pub struct ExecutionOptions {
+    pub dry_run: bool,
+    pub output: OutputMode,
+    /// Run the actions which do not depend on a failed package
+    pub keep_going: bool,
+    /// Paths of the dot packages each package depends on
+    pub dependencies: HashMap<String, Vec<String>>,
+    /// How many actions can run at the same time
+    pub jobs: usize,
+    /// Checksums of the copies and templates of the previous installation,
+    /// replacing them needs no backup
+    pub written: BTreeMap<String, String>,
+}
+
+/// Outcome of every action given to execute, in the same order
+#[derive(Debug, Default)]
+pub struct ExecutionReport {
+    pub outcomes: Vec<(SystemAction, Outcome)>,
+}
+
+impl ExecutionReport {
+    pub fn count(&self, predicate: fn(&Outcome) -> bool) -> usize {
+        return self
+            .outcomes
+            .iter()
+            .filter(|(_, outcome)| predicate(outcome))
+            .count();
+    }
+
+    pub fn has_failures(&self) -> bool {
+        return self.count(|outcome| matches!(outcome, Outcome::Failed(_))) > 0;
+    }
+
+    /// Actions which succeeded, in execution order
+    pub fn applied(&self) -> Vec<SystemAction> {
+        return self
+            .outcomes
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Succeeded))
+            .map(|(sysaction, _)| sysaction.clone())
+            .collect();
+    }
+
+    /// Actions which failed or were skipped
+    pub fn not_applied(&self) -> Vec<SystemAction> {
+        return self
+            .outcomes
+            .iter()
+            .filter(|(_, outcome)| !matches!(outcome, Outcome::Succeeded))
+            .map(|(sysaction, _)| sysaction.clone())
+            .collect();
+    }
+
+    pub fn print_summary(&self) {
+        let rows: Vec<(&str, String, String)> = self
+            .outcomes
+            .iter()
+            .map(|(sysaction, outcome)| {
+                let (operation, target) = describe(sysaction);
+                let status = match outcome {
+                    Outcome::Succeeded => "ok",
+                    Outcome::Failed(_) => "failed",
+                    Outcome::Skipped => "skipped",
+                };
+                (status, operation, target)
+            })
+            .collect();
+        let status_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+        let operation_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        println!();
+        for ((status, operation, target), (_, outcome)) in rows.iter().zip(self.outcomes.iter()) {
+            let padded_status = format!("{:width$}", status, width = status_width);
+            let colored_status = match outcome {
+                Outcome::Succeeded => padded_status.green(),
+                Outcome::Failed(_) => padded_status.red(),
+                Outcome::Skipped => padded_status.yellow(),
+            };
+            println!(
+                "{}  {:width$}  {}",
+                colored_status,
+                operation,
+                target,
+                width = operation_width
+            );
+            if let Outcome::Failed(error) = outcome {
+                println!("{:width$}  {}", "", error, width = status_width);
+            }
+        }
+        println!(
+            "{} succeeded, {} failed, {} skipped",
+            self.count(|outcome| matches!(outcome, Outcome::Succeeded)),
+            self.count(|outcome| matches!(outcome, Outcome::Failed(_))),
+            self.count(|outcome| matches!(outcome, Outcome::Skipped)),
+        );
+    }
+}
 
-use super::{FileOperation, PackageOperation, ScriptOperation, SystemAction};
+/// Operation and target of an action, for the summary and the plan
+pub fn describe(sysaction: &SystemAction) -> (String, String) {
+    return match sysaction {
+        SystemAction::Package {
+            operation,
+            source,
+            name,
+            origin: _,
+        } => (
+            format!("{:?}", operation).to_lowercase(),
+            format!("{}:{}", source, name),
+        ),
+        SystemAction::Script { origin, .. } => ("run".to_string(), origin.clone()),
+        SystemAction::File {
+            operation, dest, ..
+        } => (format!("{:?}", operation).to_lowercase(), dest.clone()),
+    };
+}
 
-pub fn execute_pm_command(command: &String, package_name: &String) {
+fn build_pm_command(command: &String, package_name: &String) -> Command {
     let args: Vec<String> = command
         .trim()
         .split(" ")
@@ -30,208 +218,639 @@ pub fn execute_pm_command(command: &String, package_name: &String) {
             std_command.arg(package_item);
         }
     }
-    handle_output(
-        std_command
-            .output()
-            .expect(&format!("\"{}\" failed to start", command)),
-    );
+    return std_command;
+}
+
+fn spawn_error(command: &String) -> impl Fn(std::io::Error) -> ExecutionError + '_ {
+    return move |error| ExecutionError::Spawn {
+        command: command.clone(),
+        error,
+    };
+}
+
+/// Print each line of a command output as soon as it is written
+fn stream_lines<R: Read>(reader: R, prefix: &str, to_stderr: bool) {
+    for line in BufReader::new(reader).split(b'\n') {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let line = String::from_utf8_lossy(&line);
+        if to_stderr {
+            eprintln!("{} {}", prefix, line);
+        } else {
+            println!("{} {}", prefix, line);
+        }
+    }
 }
 
-pub fn execute_script(script: &String, origin: &String) {
-    let filepath = pathbuf![&std::env::temp_dir(), "dotstrap-tmp-script.sh"];
-    std::fs::write(&filepath, script).expect(&format!(
+fn run_command(
+    mut std_command: Command,
+    name: &String,
+    tag: &String,
+    output: OutputMode,
+    interactive: bool,
+) -> Result<(), ExecutionError> {
+    // Prompts, like a sudo password, need the terminal
+    if interactive {
+        let status = std_command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(spawn_error(name))?;
+        return handle_status(name, status);
+    }
+    if output == OutputMode::Quiet {
+        return handle_output(name, std_command.output().map_err(spawn_error(name))?);
+    }
+    let mut child = std_command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error(name))?;
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let prefix = format!("[{}]", tag).dimmed().to_string();
+    std::thread::scope(|scope| {
+        scope.spawn(|| stream_lines(stdout, &prefix, false));
+        stream_lines(stderr, &prefix, true);
+    });
+    let status = child.wait().map_err(spawn_error(name))?;
+    return handle_status(name, status);
+}
+
+pub fn execute_pm_command(
+    command: &String,
+    package_name: &String,
+    tag: &String,
+    output: OutputMode,
+    interactive: bool,
+    escalation: Option<EscalationTool>,
+) -> Result<(), ExecutionError> {
+    let mut std_command = build_pm_command(command, package_name);
+    if let Some(tool) = escalation {
+        std_command = escalate_command(&std_command, tool);
+    }
+    return run_command(std_command, command, tag, output, interactive);
+}
+
+/// Makes the temporary files of the actions running at the same time unique
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn execute_script(
+    script: &String,
+    origin: &String,
+    env: &BTreeMap<String, String>,
+    output: OutputMode,
+    interactive: bool,
+    escalation: Option<EscalationTool>,
+) -> Result<(), ExecutionError> {
+    // Scripts may run at the same time, each one gets its own file
+    let filepath = pathbuf![
+        &std::env::temp_dir(),
+        &format!(
+            "dotstrap-tmp-script-{}-{}.sh",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        )
+    ];
+    std::fs::write(&filepath, script).context(format!(
         "Unable to write file {}",
         filepath.to_string_lossy()
-    ));
+    ))?;
     let mut perms = std::fs::metadata(&filepath)
-        .expect(&format!(
+        .context(format!(
             "Unable to read file permisions of {}",
             filepath.to_string_lossy()
-        ))
+        ))?
         .permissions();
     perms.set_mode(0o744);
-    std::fs::set_permissions(&filepath, perms).expect(&format!(
+    std::fs::set_permissions(&filepath, perms).context(format!(
         "Unable to read file permisions of {}",
         filepath.to_string_lossy()
-    ));
-    let output = Command::new("sh")
+    ))?;
+    let mut std_command = Command::new("sh");
+    std_command
+        .envs(env)
         .env("PACKAGE", origin.split(':').nth(0).unwrap())
-        .arg(filepath.to_str().unwrap().to_string())
-        .output()
-        .expect(&format!(
-            "Unable to run script in {}",
-            filepath.to_string_lossy()
-        ));
-    std::fs::remove_file(&filepath).expect(&format!(
+        .arg(filepath.to_str().unwrap().to_string());
+    if let Some(tool) = escalation {
+        std_command = escalate_command(&std_command, tool);
+    }
+    let tag = origin.rsplit(':').next().unwrap().to_uppercase();
+    let result = run_command(std_command, origin, &tag, output, interactive);
+    std::fs::remove_file(&filepath).context(format!(
         "Unable to remove script in {}",
         filepath.to_string_lossy()
-    ));
-    handle_output(output);
+    ))?;
+    return result;
 }
 
-fn handle_output(console_output: Output) {
+/// Run a package manager query command, installed when it succeeds
+pub fn is_package_installed(command: &String, package_name: &String) -> Result<bool> {
+    let output = build_pm_command(command, package_name)
+        .output()
+        .context(format!("\"{}\" failed to start", command))?;
+    return Ok(output.status.success());
+}
+
+/// Run a package manager listing command, the first word of each line is a package
+pub fn list_installed_packages(command: &String) -> Result<Vec<String>> {
+    let output = build_pm_command(command, &String::new())
+        .output()
+        .context(format!("\"{}\" failed to start", command))?;
+    if !output.status.success() {
+        bail!(
+            "\"{}\" failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|name| name.to_string())
+        .collect());
+}
+
+fn handle_status(command: &String, status: ExitStatus) -> Result<(), ExecutionError> {
+    if status.success() {
+        return Ok(());
+    }
+    return Err(ExecutionError::Status {
+        command: command.clone(),
+        code: status.code(),
+    });
+}
+
+fn handle_output(command: &String, console_output: Output) -> Result<(), ExecutionError> {
     if console_output.status.success() {
-        return;
+        return Ok(());
     }
 
-    println!("{}", String::from_utf8(console_output.stdout).unwrap());
-    eprintln!("{}", String::from_utf8(console_output.stderr).unwrap());
-    panic!["Error during command"];
+    println!("{}", String::from_utf8_lossy(&console_output.stdout));
+    eprintln!("{}", String::from_utf8_lossy(&console_output.stderr));
+    return handle_status(command, console_output.status);
 }
 
 fn log_with_tag<T: std::fmt::Display>(tag: T, message: &String) {
     println!("[{}] {}", tag, message);
 }
 
+/// A package is blocked when it or one of its dependencies failed
+fn is_blocked(
+    package_path: &str,
+    failed_packages: &Vec<String>,
+    dependencies: &HashMap<String, Vec<String>>,
+) -> bool {
+    if failed_packages.iter().any(|failed| failed == package_path) {
+        return true;
+    }
+    return dependencies.get(package_path).is_some_and(|paths| {
+        paths
+            .iter()
+            .any(|path| is_blocked(path, failed_packages, dependencies))
+    });
+}
+
+/// Progress of an execution, shared by the jobs
+struct Progress {
+    scheduler: Scheduler,
+    outcomes: Vec<Option<Outcome>>,
+    failed_packages: Vec<String>,
+}
+
+/// Run the actions one after the other until none is left
+fn run_job(
+    sytem_actions: &Vec<SystemAction>,
+    config: &HostConfiguration,
+    context: &VariableContext,
+    backups: &Mutex<&mut BackupStore>,
+    options: &ExecutionOptions,
+    progress: &Mutex<Progress>,
+    changed: &Condvar,
+) {
+    let mut state = progress.lock().unwrap();
+    loop {
+        let stop = !state.failed_packages.is_empty() && !options.keep_going;
+        for index in state.scheduler.pending() {
+            let package_path = action_package_path(&sytem_actions[index]);
+            if stop || is_blocked(package_path, &state.failed_packages, &options.dependencies) {
+                state.scheduler.skip(index);
+                state.outcomes[index] = Some(Outcome::Skipped);
+            }
+        }
+        if state.scheduler.is_done() {
+            changed.notify_all();
+            return;
+        }
+        let index = match state.scheduler.start_next() {
+            Some(index) => index,
+            None => {
+                state = changed.wait(state).unwrap();
+                continue;
+            }
+        };
+        drop(state);
+        let sysaction = &sytem_actions[index];
+        let result = execute_action(sysaction, config, context, backups, options);
+        state = progress.lock().unwrap();
+        state.scheduler.finish(index);
+        state.outcomes[index] = Some(match result {
+            Ok(()) => Outcome::Succeeded,
+            Err(error) => {
+                log_with_tag("FAILED".red(), &error.to_string());
+                let package_path = action_package_path(sysaction).to_string();
+                state.failed_packages.push(package_path);
+                Outcome::Failed(error)
+            }
+        });
+        changed.notify_all();
+    }
+}
+
+/// Run the actions with up to options.jobs of them at the same time
 pub fn execute(
     sytem_actions: &Vec<SystemAction>,
     config: &HostConfiguration,
-    dry_run: bool,
-) -> Result<()> {
-    let really_execute = !dry_run;
-    for sysaction in sytem_actions.into_iter() {
-        match sysaction {
-            SystemAction::Package {
-                operation,
+    context: &VariableContext,
+    backups: &mut BackupStore,
+    options: &ExecutionOptions,
+) -> ExecutionReport {
+    let progress = Mutex::new(Progress {
+        scheduler: Scheduler::new(sytem_actions, config, &options.dependencies),
+        outcomes: sytem_actions.iter().map(|_| None).collect(),
+        failed_packages: vec![],
+    });
+    let changed = Condvar::new();
+    let backups = Mutex::new(backups);
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs.max(1) {
+            scope.spawn(|| {
+                run_job(
+                    sytem_actions,
+                    config,
+                    context,
+                    &backups,
+                    options,
+                    &progress,
+                    &changed,
+                )
+            });
+        }
+    });
+    let outcomes = progress.into_inner().unwrap().outcomes;
+    return ExecutionReport {
+        outcomes: sytem_actions
+            .iter()
+            .cloned()
+            .zip(outcomes.into_iter().map(|outcome| outcome.unwrap()))
+            .collect(),
+    };
+}
+
+/// File operations, run through the escalation tool for the files owned by root
+struct FileSystem {
+    escalation: Option<EscalationTool>,
+}
+
+impl FileSystem {
+    fn run_escalated(&self, tool: EscalationTool, args: &[&OsStr]) -> Result<(), ExecutionError> {
+        let mut std_command = Command::new(args[0]);
+        std_command.args(&args[1..]);
+        let name = args
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        return run_command(
+            escalate_command(&std_command, tool),
+            &name,
+            &"BECOME".to_string(),
+            OutputMode::Quiet,
+            false,
+        );
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> Result<(), ExecutionError> {
+        if let Some(tool) = self.escalation {
+            return self.run_escalated(tool, &["mkdir".as_ref(), "-p".as_ref(), dir.as_ref()]);
+        }
+        std::fs::create_dir_all(dir).context(format!(
+            "Unable to make directory at {}",
+            dir.to_str().unwrap()
+        ))?;
+        return Ok(());
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), ExecutionError> {
+        if let Some(tool) = self.escalation {
+            return self.run_escalated(tool, &["rm".as_ref(), "-f".as_ref(), path.as_ref()]);
+        }
+        std::fs::remove_file(path)
+            .context(format!("Unable to remove file {}", path.to_str().unwrap()))?;
+        return Ok(());
+    }
+
+    fn symlink(&self, src: &Path, dest: &Path) -> Result<(), ExecutionError> {
+        if let Some(tool) = self.escalation {
+            return self.run_escalated(
+                tool,
+                &["ln".as_ref(), "-s".as_ref(), src.as_ref(), dest.as_ref()],
+            );
+        }
+        std::os::unix::fs::symlink(src, dest).context(format!(
+            "Unable to symlink from {} to {}",
+            src.to_str().unwrap(),
+            dest.to_str().unwrap()
+        ))?;
+        return Ok(());
+    }
+
+    fn copy(&self, src: &Path, dest: &Path) -> Result<(), ExecutionError> {
+        if let Some(tool) = self.escalation {
+            return self.run_escalated(tool, &["cp".as_ref(), src.as_ref(), dest.as_ref()]);
+        }
+        std::fs::copy(src, dest).context(format!(
+            "Unable to copy from {} to {}",
+            src.to_str().unwrap(),
+            dest.to_str().unwrap()
+        ))?;
+        return Ok(());
+    }
+
+    fn write(&self, dest: &Path, content: &String) -> Result<(), ExecutionError> {
+        if self.escalation.is_some() {
+            // Written by the user first, then copied in place by root
+            let rendered_path = pathbuf![
+                &std::env::temp_dir(),
+                &format!(
+                    "dotstrap-tmp-template-{}-{}",
+                    std::process::id(),
+                    TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+                )
+            ];
+            std::fs::write(&rendered_path, content).context(format!(
+                "Unable to write file {}",
+                rendered_path.to_string_lossy()
+            ))?;
+            let result = self.copy(&rendered_path, dest);
+            let _ = std::fs::remove_file(&rendered_path);
+            return result;
+        }
+        std::fs::write(dest, content)
+            .context(format!("Unable to write file {}", dest.to_str().unwrap()))?;
+        return Ok(());
+    }
+
+    fn backup(
+        &self,
+        backups: &Mutex<&mut BackupStore>,
+        dest: &Path,
+    ) -> Result<PathBuf, ExecutionError> {
+        let tool = match self.escalation {
+            Some(tool) => tool,
+            None => return Ok(backups.lock().unwrap().backup(dest)?),
+        };
+        let backup_path = backups.lock().unwrap().backup_path(dest)?;
+        let backup_dir = backup_path.parent().unwrap();
+        std::fs::create_dir_all(backup_dir).context(format!(
+            "Unable to make directory at {}",
+            backup_dir.to_string_lossy()
+        ))?;
+        self.run_escalated(tool, &["mv".as_ref(), dest.as_ref(), backup_path.as_ref()])?;
+        backups.lock().unwrap().record(dest, &backup_path);
+        return Ok(backup_path);
+    }
+
+    fn restore(&self, backup: &Path, dest: &Path) -> Result<(), ExecutionError> {
+        let tool = match self.escalation {
+            Some(tool) => tool,
+            None => return Ok(BackupStore::restore(backup, dest)?),
+        };
+        if !backup.exists() {
+            return Err(anyhow!("Backup {} does not exist", backup.to_string_lossy()).into());
+        }
+        self.run_escalated(tool, &["rm".as_ref(), "-rf".as_ref(), dest.as_ref()])?;
+        return self.run_escalated(tool, &["mv".as_ref(), backup.as_ref(), dest.as_ref()]);
+    }
+}
+
+fn execute_action(
+    sysaction: &SystemAction,
+    config: &HostConfiguration,
+    context: &VariableContext,
+    backups: &Mutex<&mut BackupStore>,
+    options: &ExecutionOptions,
+) -> Result<(), ExecutionError> {
+    let really_execute = !options.dry_run;
+    match sysaction {
+        SystemAction::Package {
+            operation,
+            source,
+            name,
+            origin,
+        } => {
+            let pm = config.package_managers.get(source).ok_or(anyhow!(
+                "Invalid source {} from {}",
                 source,
-                name,
-                origin,
-            } => {
-                let pm = config
-                    .package_managers
-                    .get(source)
-                    .expect(&format!("Invalid source {} from {}", source, origin));
-                let long_package_name = if "os".eq(source) {
-                    name.clone()
-                } else {
-                    format!("{}:{}", source, name)
-                };
-                match operation {
-                    PackageOperation::Install => {
-                        log_with_tag("INSTALL".green(), &long_package_name);
-                        if really_execute {
-                            execute_pm_command(&pm.commands.install, name);
-                        }
+                origin
+            ))?;
+            let long_package_name = if "os".eq(source) {
+                name.clone()
+            } else {
+                format!("{}:{}", source, name)
+            };
+            match operation {
+                PackageOperation::Install => {
+                    log_with_tag("INSTALL".green(), &long_package_name);
+                    if really_execute {
+                        execute_pm_command(
+                            &pm.commands.install,
+                            name,
+                            &"INSTALL".to_string(),
+                            options.output,
+                            pm.interactive,
+                            pm.escalate.then_some(config.escalation),
+                        )?;
                     }
-                    PackageOperation::Uninstall => {
-                        log_with_tag("REMOVE".red(), &long_package_name);
-                        if really_execute {
-                            execute_pm_command(&pm.commands.uninstall, name);
-                        }
+                }
+                PackageOperation::Uninstall => {
+                    log_with_tag("REMOVE".red(), &long_package_name);
+                    if really_execute {
+                        execute_pm_command(
+                            &pm.commands.uninstall,
+                            name,
+                            &"REMOVE".to_string(),
+                            options.output,
+                            pm.interactive,
+                            pm.escalate.then_some(config.escalation),
+                        )?;
                     }
                 }
             }
-            SystemAction::Script {
-                operation,
-                script,
-                origin,
-            } => match operation {
-                ScriptOperation::Run => {
+        }
+        SystemAction::Script {
+            operation,
+            script,
+            origin,
+            policy: _,
+            checksum: _,
+            interactive,
+            escalate,
+        } => match operation {
+            ScriptOperation::Run => {
+                log_with_tag(
+                    origin
+                        .split(":")
+                        .last()
+                        .unwrap()
+                        .to_uppercase()
+                        .bright_purple(),
+                    &script.trim().to_string(),
+                );
+                if really_execute {
+                    let package_path = origin.split(':').next().unwrap();
+                    execute_script(
+                        script,
+                        origin,
+                        &context.exported(package_path),
+                        options.output,
+                        *interactive,
+                        escalate.then_some(config.escalation),
+                    )?;
+                }
+            }
+            // Turned into Run by the lockfile diff when the package is removed
+            ScriptOperation::OnUninstall => {}
+        },
+        SystemAction::File {
+            operation,
+            src,
+            dest,
+            origin,
+            checksum: _,
+            backup,
+            escalate,
+        } => {
+            let src_path = pathbuf![&std::env::current_dir().unwrap(), &origin, src];
+            let dest_path = pathbuf![dest];
+            let dest_dir = dest_path.parent().unwrap().to_path_buf();
+            let files = FileSystem {
+                escalation: escalate.then_some(config.escalation),
+            };
+            match operation {
+                FileOperation::Link => {
                     log_with_tag(
-                        origin
-                            .split(":")
-                            .last()
-                            .unwrap()
-                            .to_uppercase()
-                            .bright_purple(),
-                        &script.trim().to_string(),
+                        "LINK".blue(),
+                        &format!(
+                            "{} {}",
+                            src_path.to_str().unwrap(),
+                            dest_path.to_str().unwrap()
+                        ),
                     );
                     if really_execute {
-                        execute_script(script, origin);
+                        // Create dir if not exist
+                        if !dest_dir.exists() {
+                            println!("create dir at {}", dest_path.to_str().unwrap());
+                            files.create_dir_all(&dest_dir)?;
+                        }
+                        // Save existing file or remove existing symlink before symlink
+                        if dest_path.is_symlink() {
+                            println!("remove file at {}", dest_path.to_str().unwrap());
+                            files.remove_file(&dest_path)?;
+                        } else if dest_path.exists() {
+                            let backup_path = files.backup(backups, &dest_path)?;
+                            println!("backup file at {}", backup_path.to_str().unwrap());
+                        }
+                        files.symlink(&src_path, &dest_path)?;
                     }
                 }
-            },
-            SystemAction::File {
-                operation,
-                src,
-                dest,
-                origin,
-            } => {
-                let src_path = pathbuf![&std::env::current_dir().unwrap(), &origin, src];
-                let dest_path = pathbuf![dest];
-                let dest_dir = dest_path.parent().unwrap().to_path_buf();
-                match operation {
-                    FileOperation::Link => {
-                        log_with_tag(
-                            "LINK".blue(),
-                            &format!(
-                                "{} {}",
-                                src_path.to_str().unwrap(),
-                                dest_path.to_str().unwrap()
-                            ),
-                        );
-                        if really_execute {
-                            // Create dir if not exist
-                            if !dest_dir.exists() {
-                                println!("create dir at {}", dest_path.to_str().unwrap());
-                                std::fs::create_dir_all(&dest_dir).expect(&format!(
-                                    "Unable to make directory at {}",
-                                    dest_dir.to_str().unwrap()
-                                ));
-                            }
-                            // Remove existing file before symlink
-                            if dest_path.metadata().is_ok() {
-                                println!("remove file at {}", dest_path.to_str().unwrap());
-                                std::fs::remove_file(&dest_path).expect(&format!(
-                                    "Unable to remove file {}",
-                                    dest_path.to_str().unwrap()
-                                ));
+                FileOperation::Copy => {
+                    log_with_tag(
+                        "COPY".blue(),
+                        &format!(
+                            "{} {}",
+                            src_path.to_str().unwrap(),
+                            dest_path.to_str().unwrap()
+                        ),
+                    );
+                    if really_execute {
+                        // Create dir if not exist
+                        if !dest_dir.exists() {
+                            files.create_dir_all(&dest_dir)?;
+                        }
+                        // Remove existing symlink before creating file
+                        if dest_path.is_symlink() && dest_path.symlink_metadata().is_ok() {
+                            println!("remove file at {}", dest_path.to_str().unwrap());
+                            files.remove_file(&dest_path)?;
+                        }
+                        // Save a file which differs from the source, unless dotstrap wrote it
+                        let dest_checksum = dest_path
+                            .metadata()
+                            .ok()
+                            .and_then(|_| checksum_file(&dest_path));
+                        let is_up_to_date =
+                            dest_checksum.is_some() && dest_checksum == checksum_file(&src_path);
+                        if !is_up_to_date {
+                            if dest_checksum.is_some()
+                                && dest_checksum.as_ref() != options.written.get(dest)
+                            {
+                                let backup_path = files.backup(backups, &dest_path)?;
+                                println!("backup file at {}", backup_path.to_str().unwrap());
                             }
-                            std::os::unix::fs::symlink(&src_path, &dest_path).expect(&format!(
-                                "Unable to symlink from {} to {}",
-                                src_path.to_str().unwrap(),
-                                dest_dir.to_str().unwrap()
-                            ));
+                            files.copy(&src_path, &dest_path)?;
                         }
                     }
-                    FileOperation::Copy => {
-                        log_with_tag(
-                            "COPY".blue(),
-                            &format!(
-                                "{} {}",
-                                src_path.to_str().unwrap(),
-                                dest_path.to_str().unwrap()
-                            ),
-                        );
-                        if really_execute {
-                            // Create dir if not exist
-                            if !dest_dir.exists() {
-                                std::fs::create_dir_all(&dest_dir).expect(&format!(
-                                    "Unable to make directory at {}",
-                                    dest_dir.to_str().unwrap()
-                                ));
-                            }
-                            // Remove existing symlink before creating file
-                            if dest_path.is_symlink() && dest_path.symlink_metadata().is_ok() {
-                                println!("remove file at {}", dest_path.to_str().unwrap());
-                                std::fs::remove_file(&dest_path).expect(&format!(
-                                    "Unable to remove file {}",
-                                    dest_path.to_str().unwrap()
-                                ));
-                            }
-                            // Do not overwrite file
-                            if dest_path.metadata().is_err() {
-                                std::fs::copy(&src_path, &dest_path).expect(&format!(
-                                    "Unable to copy from {} to {}",
-                                    src_path.to_str().unwrap(),
-                                    dest_dir.to_str().unwrap()
-                                ));
-                            }
+                }
+                FileOperation::Template => {
+                    log_with_tag(
+                        "TEMPLATE".blue(),
+                        &format!(
+                            "{} {}",
+                            src_path.to_str().unwrap(),
+                            dest_path.to_str().unwrap()
+                        ),
+                    );
+                    if really_execute {
+                        let rendered = render_file(&src_path, &context.for_package(origin))?;
+                        // Create dir if not exist
+                        if !dest_dir.exists() {
+                            files.create_dir_all(&dest_dir)?;
                         }
+                        // Remove existing symlink or save a foreign file before writing
+                        if dest_path.is_symlink() {
+                            println!("remove file at {}", dest_path.to_str().unwrap());
+                            files.remove_file(&dest_path)?;
+                        } else if dest_path.exists()
+                            && std::fs::read_to_string(&dest_path).ok().as_ref() != Some(&rendered)
+                            && checksum_file(&dest_path).as_ref() != options.written.get(dest)
+                        {
+                            let backup_path = files.backup(backups, &dest_path)?;
+                            println!("backup file at {}", backup_path.to_str().unwrap());
+                        }
+                        files.write(&dest_path, &rendered)?;
                     }
-                    FileOperation::Remove => {
-                        log_with_tag(
-                            "DELETE".bright_red(),
-                            &format!("{}", dest_path.to_str().unwrap()),
-                        );
-                        if really_execute && dest_path.exists() {
-                            std::fs::remove_file(&dest_path)
-                                .expect(&format!("Unable to remove file {}", dest));
+                }
+                FileOperation::Remove => {
+                    log_with_tag(
+                        "DELETE".bright_red(),
+                        &format!("{}", dest_path.to_str().unwrap()),
+                    );
+                    if really_execute && dest_path.symlink_metadata().is_ok() {
+                        files.remove_file(&dest_path)?;
+                    }
+                    if let Some(backup) = backup {
+                        log_with_tag("RESTORE".green(), &format!("{} {}", backup, dest));
+                        if really_execute {
+                            files.restore(&pathbuf![backup], &dest_path)?;
                         }
                     }
                 }
             }
         }
     }
-    Ok(())
+    return Ok(());
 }
@@ -0,0 +1,65 @@
+use anyhow::{bail, Result};
+
+use crate::host::config::HostConfiguration;
+
+use super::{scheduler::action_package_path, SystemAction};
+
+/// Action types which are not package managers
+const ACTION_TYPES: &[&str] = &["dot", "link", "script"];
+
+/// Kinds of actions an installation is limited to
+#[derive(Debug, Default, Clone)]
+pub struct ActionFilter {
+    /// Only the packages
+    pub system: bool,
+    /// Only the dotfiles
+    pub user: bool,
+    /// Package managers, dot, link or script
+    pub skip: Vec<String>,
+}
+
+impl ActionFilter {
+    /// Fails on a skipped type which is neither a package manager nor an action type
+    pub fn check(&self, config: &HostConfiguration) -> Result<()> {
+        for skipped in self.skip.iter() {
+            if !ACTION_TYPES.contains(&skipped.as_str())
+                && !config.package_managers.contains_key(skipped)
+            {
+                let mut expected: Vec<&str> = config
+                    .package_managers
+                    .keys()
+                    .map(|name| name.as_str())
+                    .collect();
+                expected.sort();
+                expected.extend(ACTION_TYPES);
+                bail!(
+                    "Unknown type {} to skip, expected one of {}",
+                    skipped,
+                    expected.join(", ")
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn accepts(&self, sysaction: &SystemAction) -> bool {
+        let action_type = match sysaction {
+            SystemAction::Package { source, .. } => source.as_str(),
+            SystemAction::Script { .. } => "script",
+            SystemAction::File { .. } => "link",
+        };
+        if self.skip.iter().any(|skipped| skipped == action_type) {
+            return false;
+        }
+        if self.skip.iter().any(|skipped| skipped == "dot")
+            && action_package_path(sysaction).starts_with("packages/")
+        {
+            return false;
+        }
+        return match sysaction {
+            SystemAction::Package { .. } => !self.user,
+            SystemAction::Script { .. } => !self.user && !self.system,
+            SystemAction::File { .. } => !self.system,
+        };
+    }
+}
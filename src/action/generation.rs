@@ -1,9 +1,21 @@
 use crate::{
-    action::{FileOperation, PackageOperation, ScriptOperation, SystemAction},
+    action::{
+        list_tree_files, template::render_file, FileOperation, PackageOperation, ScriptOperation,
+        SystemAction,
+    },
     host::config::HostConfiguration,
+    variables::VariableContext,
 };
-use anyhow::Result;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use pathbuf::pathbuf;
+use std::{collections::HashSet, os::unix::fs::PermissionsExt, path::Path};
+
+/// Directory the bundled files are unpacked into, unless DOTSTRAP_BUNDLE_DIR is set
+const BUNDLE_DIR: &str = "$HOME/.local/share/dotstrap/bundle";
+
+/// Line length of the embedded base64, as base64 wraps it
+const BUNDLE_LINE_LENGTH: usize = 76;
 
 fn add_comment(new_comment: String, last_comment: &String, output: &mut Vec<String>) -> String {
     if new_comment.ne(last_comment) {
@@ -13,12 +25,77 @@ fn add_comment(new_comment: String, last_comment: &String, output: &mut Vec<Stri
     return last_comment.clone();
 }
 
+/// Escalation tool to put in front of a command needing root
+fn become_prefix(escalate: bool, config: &HostConfiguration) -> String {
+    if !escalate {
+        return String::new();
+    }
+    return format!("{} ", config.escalation.program());
+}
+
+/// Where a file of the repository is unpacked by the script
+fn bundle_path(src_path: &Path) -> String {
+    return format!(
+        "$BUNDLE_DIR/{}",
+        src_path.to_string_lossy().trim_start_matches('/')
+    );
+}
+
+/// Embed a file of the repository, unpacked under $BUNDLE_DIR when the script runs
+fn bundle_file(src_path: &Path, output: &mut Vec<String>) -> Result<()> {
+    let content = std::fs::read(src_path).context(format!(
+        "Unable to read {} to bundle it",
+        src_path.to_string_lossy()
+    ))?;
+    let encoded = STANDARD.encode(content);
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(BUNDLE_LINE_LENGTH)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap())
+        .collect();
+    if let Some(parent) = src_path.parent() {
+        output.push(format!("mkdir -p \"{}\"", bundle_path(parent)));
+    }
+    output.push(format!(
+        "base64 -d > \"{}\" <<'DOTSTRAP_EOF'\n{}\nDOTSTRAP_EOF",
+        bundle_path(src_path),
+        lines.join("\n")
+    ));
+    if std::fs::metadata(src_path)?.permissions().mode() & 0o111 != 0 {
+        output.push(format!("chmod +x \"{}\"", bundle_path(src_path)));
+    }
+    return Ok(());
+}
+
+/// Embed a linked or copied source, a directory with every file under it
+fn bundle_source(src_path: &Path, output: &mut Vec<String>) -> Result<()> {
+    if !src_path.is_dir() {
+        return bundle_file(src_path, output);
+    }
+    output.push(format!("mkdir -p \"{}\"", bundle_path(src_path)));
+    for file in list_tree_files(src_path)?.iter() {
+        bundle_file(&src_path.join(file), output)?;
+    }
+    return Ok(());
+}
+
 pub fn generate_shell_script(
     sytem_actions: &Vec<SystemAction>,
     config: &HostConfiguration,
+    context: &VariableContext,
+    bundle: bool,
 ) -> Result<Vec<String>> {
     let mut last_comment = String::new();
     let mut output = vec!["# Shell Generation".to_string()];
+    // Files already embedded in the script
+    let mut bundled: HashSet<String> = HashSet::new();
+    if bundle {
+        output.push("set -e".to_string());
+        output.push(format!(
+            "BUNDLE_DIR=\"${{DOTSTRAP_BUNDLE_DIR:-{}}}\"",
+            BUNDLE_DIR
+        ));
+    }
     for sysaction in sytem_actions.into_iter() {
         match sysaction {
             SystemAction::Package {
@@ -36,47 +113,106 @@ pub fn generate_shell_script(
                     &last_comment,
                     &mut output,
                 );
-                output.push(match operation {
+                let command = match operation {
                     PackageOperation::Install => pm.commands.install.replace("<package>", &name),
                     PackageOperation::Uninstall => {
                         pm.commands.uninstall.replace("<package>", &name)
                     }
-                });
+                };
+                output.push(format!("{}{}", become_prefix(pm.escalate, config), command));
             }
             SystemAction::Script {
                 operation,
                 script,
                 origin,
+                policy: _,
+                checksum: _,
+                interactive: _,
+                escalate,
             } => match operation {
                 ScriptOperation::Run => {
                     last_comment = add_comment(format!("# {}", origin), &last_comment, &mut output);
-                    output.push(format!(
-                        "PACKAGE={}\n{}",
-                        origin.split(':').nth(0).unwrap(),
-                        script
-                    ));
+                    let package_path = origin.split(':').nth(0).unwrap();
+                    for (name, value) in context.exported(package_path) {
+                        output.push(format!(
+                            "export {}='{}'",
+                            name,
+                            value.replace('\'', "'\\''")
+                        ));
+                    }
+                    if *escalate {
+                        // The escalation tool resets the environment
+                        let envs: Vec<String> = context
+                            .exported(package_path)
+                            .keys()
+                            .map(|name| format!("{}=\"${}\"", name, name))
+                            .collect();
+                        output.push(format!(
+                            "{}env {} PACKAGE={} sh <<'DOTSTRAP_EOF'\n{}\nDOTSTRAP_EOF",
+                            become_prefix(true, config),
+                            envs.join(" "),
+                            package_path,
+                            script.trim_end_matches('\n')
+                        ));
+                    } else {
+                        output.push(format!("PACKAGE={}\n{}", package_path, script));
+                    }
                 }
+                ScriptOperation::OnUninstall => {}
             },
             SystemAction::File {
                 operation,
                 src,
                 dest,
                 origin,
+                checksum: _,
+                backup: _,
+                escalate,
             } => {
+                let prefix = become_prefix(*escalate, config);
                 last_comment =
                     add_comment(format!("# {}:links", &origin), &last_comment, &mut output);
                 let src_path = pathbuf![&origin, src];
+                // The linked and copied files come from the bundle or from the repository
+                let src_path_string = if bundle {
+                    format!("\"{}\"", bundle_path(&src_path))
+                } else {
+                    format!("\"$PWD/{}\"", src_path.to_string_lossy())
+                };
+                if bundle
+                    && matches!(operation, FileOperation::Link | FileOperation::Copy)
+                    && bundled.insert(src_path.to_string_lossy().to_string())
+                {
+                    bundle_source(&src_path, &mut output)?;
+                }
+                if operation != &FileOperation::Remove {
+                    if let Some(parent) = Path::new(dest).parent() {
+                        output.push(format!("{}mkdir -p {}", prefix, parent.to_string_lossy()));
+                    }
+                }
                 output.push(match operation {
                     FileOperation::Link => {
-                        let src_path_string = src_path.to_str().unwrap();
-                        format!("ln -sf {} {}", dest, src_path_string)
+                        format!("{}ln -sf {} {}", prefix, src_path_string, dest)
                     }
                     FileOperation::Copy => {
-                        let src_path_string = src_path.to_str().unwrap();
-                        format!("cp -n {} {}", src_path_string, dest)
+                        format!("{}cp -n {} {}", prefix, src_path_string, dest)
+                    }
+                    FileOperation::Template => {
+                        let rendered = render_file(&src_path, &context.for_package(origin))?;
+                        // A redirection would be done by the shell, not by root
+                        let writer = if *escalate {
+                            format!("{}tee {} > /dev/null", prefix, dest)
+                        } else {
+                            format!("cat > {}", dest)
+                        };
+                        format!(
+                            "{} <<'DOTSTRAP_EOF'\n{}\nDOTSTRAP_EOF",
+                            writer,
+                            rendered.trim_end_matches('\n')
+                        )
                     }
                     FileOperation::Remove => {
-                        format!("rm -f {}", dest)
+                        format!("{}rm -f {}", prefix, dest)
                     }
                 });
             }
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use crate::host::config::HostConfiguration;
+
+use super::execution::{is_package_installed, list_installed_packages};
+
+/// Ask the package managers which packages are on the system,
+/// each listing command is run once
+pub struct InstalledPackages<'a> {
+    config: &'a HostConfiguration,
+    listed: HashMap<String, Vec<String>>,
+}
+
+impl<'a> InstalledPackages<'a> {
+    pub fn new(config: &'a HostConfiguration) -> InstalledPackages<'a> {
+        return InstalledPackages {
+            config,
+            listed: HashMap::new(),
+        };
+    }
+
+    /// None when the package manager has no way to tell
+    pub fn is_installed(&mut self, source: &String, name: &String) -> Result<Option<bool>> {
+        let commands = match self.config.package_managers.get(source) {
+            Some(pm) => &pm.commands,
+            None => return Ok(None),
+        };
+        if let Some(list_installed) = &commands.list_installed {
+            if !self.listed.contains_key(source) {
+                self.listed
+                    .insert(source.clone(), list_installed_packages(list_installed)?);
+            }
+            return Ok(Some(self.listed[source].contains(name)));
+        }
+        if let Some(query) = &commands.query {
+            return Ok(Some(is_package_installed(query, name)?));
+        }
+        return Ok(None);
+    }
+}
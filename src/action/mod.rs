@@ -1,11 +1,21 @@
+use crate::condition;
 use crate::host::config::HostConfiguration;
-use crate::package::PackageCollection;
 use crate::package::PackageDefinition;
-use anyhow::Result;
+use crate::package::{LinkFileDefinition, LinkMode, RunPolicy, ScriptDefinition};
+use crate::resolver::DependencyGraph;
+use crate::variables::{VariableContext, Variables};
+use anyhow::{Context, Result};
+use pathbuf::pathbuf;
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
+pub mod escalation;
 pub mod execution;
+pub mod filter;
 pub mod generation;
+pub mod installed;
+pub mod scheduler;
+pub mod template;
 
 #[cfg(test)]
 mod test;
@@ -19,12 +29,15 @@ pub enum PackageOperation {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum ScriptOperation {
     Run,
+    /// Recorded in the lockfile, run when the package is uninstalled
+    OnUninstall,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum FileOperation {
     Link,
     Copy,
+    Template,
     Remove,
 }
 
@@ -41,12 +54,32 @@ pub enum SystemAction {
         operation: ScriptOperation,
         script: String,
         origin: String,
+        #[serde(default, skip_serializing_if = "RunPolicy::is_onchange")]
+        policy: RunPolicy,
+        /// Checksum of the script and its watched files
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksum: Option<String>,
+        /// Run attached to the terminal instead of streaming its output
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        interactive: bool,
+        /// Run with the escalation tool
+        #[serde(default, rename = "become", skip_serializing_if = "std::ops::Not::not")]
+        escalate: bool,
     },
     File {
         operation: FileOperation,
         src: String,
         dest: String,
         origin: String,
+        /// Checksum of the rendered content for templates
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksum: Option<String>,
+        /// Where the file previously at dest has been saved
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        backup: Option<String>,
+        /// Created with the escalation tool
+        #[serde(default, rename = "become", skip_serializing_if = "std::ops::Not::not")]
+        escalate: bool,
     },
 }
 
@@ -143,9 +176,55 @@ pub fn compact_mergeable_actions(
     return merged_actions;
 }
 
+fn is_enabled(
+    when: &Option<String>,
+    variables: &Variables,
+    package: &PackageDefinition,
+) -> Result<bool> {
+    return condition::matches(when, variables)
+        .context(format!("Invalid when condition in {}", package.path));
+}
+
+fn transform_script_to_action(
+    script: &Option<ScriptDefinition>,
+    operation: ScriptOperation,
+    hook: &str,
+    package: &PackageDefinition,
+    context: &VariableContext,
+) -> Result<Option<SystemAction>> {
+    let script = match script {
+        Some(script) if is_enabled(&script.when, &context.for_package(&package.path), package)? => {
+            script
+        }
+        _ => return Ok(None),
+    };
+    let checksum = if script.watch.is_empty() {
+        None
+    } else {
+        let mut content = script.run.clone().into_bytes();
+        for watched in script.watch.iter() {
+            let watched_path = pathbuf![&package.path, &context.expand(&package.path, watched)?];
+            content.extend(watched_path.to_string_lossy().as_bytes());
+            content.extend(std::fs::read(&watched_path).unwrap_or_default());
+        }
+        Some(crate::helpers::checksum(&content))
+    };
+    return Ok(Some(SystemAction::Script {
+        operation,
+        script: script.run.clone(),
+        origin: format!("{}:{}", package.path, hook),
+        policy: script.policy,
+        checksum,
+        interactive: script.interactive,
+        escalate: script.escalate,
+    }));
+}
+
+/// Names of the dot packages a package depends on, whose condition matches
 fn transform_package_deps_to_actions(
     package: &PackageDefinition,
-    repo: &PackageCollection,
+    graph: &DependencyGraph,
+    context: &VariableContext,
     loaded: &mut Vec<String>,
 ) -> Result<Vec<SystemAction>> {
     let mut system_actions = vec![];
@@ -154,8 +233,12 @@ fn transform_package_deps_to_actions(
         return Ok(system_actions);
     }
 
+    let variables = context.for_package(&package.path);
     let package_deps = &package.dependencies;
     for dependency in package_deps.into_iter() {
+        if !is_enabled(&dependency.when, &variables, package)? {
+            continue;
+        }
         let dep_name = &dependency.name;
         let dep_src = &dependency.source;
         if dep_src.eq("dot") {
@@ -165,8 +248,9 @@ fn transform_package_deps_to_actions(
             loaded.push(dep_name.clone());
             // Load package
             system_actions.append(&mut transform_package_to_actions(
-                repo.get(dep_name).unwrap(),
-                repo,
+                graph.packages.get(dep_name).unwrap(),
+                graph,
+                context,
                 loaded,
             )?);
             continue;
@@ -182,52 +266,156 @@ fn transform_package_deps_to_actions(
     return Ok(system_actions);
 }
 
+fn transform_file_to_action(
+    link: &LinkFileDefinition,
+    src: String,
+    dest: String,
+    package: &PackageDefinition,
+    variables: &Variables,
+) -> Result<SystemAction> {
+    let (operation, checksum) = if link.template {
+        let rendered = template::render_file(&pathbuf![&package.path, &src], variables)?;
+        (
+            FileOperation::Template,
+            Some(crate::helpers::checksum(rendered.as_bytes())),
+        )
+    } else if link.copy {
+        (FileOperation::Copy, None)
+    } else {
+        (FileOperation::Link, None)
+    };
+    return Ok(SystemAction::File {
+        operation,
+        src,
+        dest,
+        origin: package.path.to_string(),
+        checksum,
+        backup: None,
+        escalate: link.escalate,
+    });
+}
+
+/// List the files of a directory recursively, relative to it and sorted
+fn list_tree_files(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative_dir) = pending.pop() {
+        let entries = std::fs::read_dir(directory.join(&relative_dir)).context(format!(
+            "Unable to read directory {}",
+            directory.join(&relative_dir).to_string_lossy()
+        ))?;
+        for entry in entries {
+            let entry = entry?;
+            let relative_path = relative_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                pending.push(relative_path);
+            } else {
+                files.push(relative_path);
+            }
+        }
+    }
+    files.sort();
+    return Ok(files);
+}
+
+/// Move the destination of every file under an alternate root directory
+pub fn prefix_root(system_actions: Vec<SystemAction>, root: &Path) -> Vec<SystemAction> {
+    return system_actions
+        .into_iter()
+        .map(|mut sysaction| {
+            if let SystemAction::File { dest, .. } = &mut sysaction {
+                *dest = root
+                    .join(dest.trim_start_matches('/'))
+                    .to_string_lossy()
+                    .to_string();
+            }
+            return sysaction;
+        })
+        .collect();
+}
+
+/// Transform the host package and the dot packages it enables, each dot package
+/// between the pre_install and the post_install of the first package depending on it
+pub fn transform_host_to_actions(
+    host_package: &PackageDefinition,
+    graph: &DependencyGraph,
+    context: &VariableContext,
+) -> Result<Vec<SystemAction>> {
+    return transform_package_to_actions(host_package, graph, context, &mut vec![]);
+}
+
 pub fn transform_package_to_actions(
     package: &PackageDefinition,
-    repo: &PackageCollection,
+    graph: &DependencyGraph,
+    context: &VariableContext,
     loaded: &mut Vec<String>,
 ) -> Result<Vec<SystemAction>> {
+    let variables = context.for_package(&package.path);
     let mut package_actions: Vec<SystemAction> = vec![];
-    if package.pre_install.is_some() {
-        package_actions.push(SystemAction::Script {
-            operation: ScriptOperation::Run,
-            script: package.pre_install.clone().unwrap(),
-            origin: format!("{}:pre_install", package.path),
-        });
-    }
+    package_actions.extend(transform_script_to_action(
+        &package.pre_install,
+        ScriptOperation::Run,
+        "pre_install",
+        package,
+        context,
+    )?);
     package_actions.append(&mut transform_package_deps_to_actions(
-        package, repo, loaded,
+        package, graph, context, loaded,
     )?);
-    if package.post_install.is_some() {
-        package_actions.push(SystemAction::Script {
-            operation: ScriptOperation::Run,
-            script: package.post_install.clone().unwrap(),
-            origin: format!("{}:post_install", package.path),
-        });
-    }
-    if package.links.len() > 0 {
-        package_actions.append(
-            &mut (&package.links)
-                .into_iter()
-                .map(|link| SystemAction::File {
-                    operation: if link.copy {
-                        FileOperation::Copy
-                    } else {
-                        FileOperation::Link
-                    },
-                    src: shellexpand::full(&link.src).unwrap().to_string(),
-                    dest: shellexpand::full(&link.dest).unwrap().to_string(),
-                    origin: package.path.to_string(),
-                })
-                .collect::<Vec<SystemAction>>(),
-        );
-    }
-    if package.post_links.is_some() {
-        package_actions.push(SystemAction::Script {
-            operation: ScriptOperation::Run,
-            script: package.post_links.clone().unwrap(),
-            origin: format!("{}:post_links", package.path),
-        });
+    package_actions.extend(transform_script_to_action(
+        &package.post_install,
+        ScriptOperation::Run,
+        "post_install",
+        package,
+        context,
+    )?);
+    for link in (&package.links).into_iter() {
+        if !is_enabled(&link.when, &variables, package)? {
+            continue;
+        }
+        let src = context.expand(&package.path, &link.src)?;
+        let dest = context.expand(&package.path, &link.dest)?;
+        match link.mode {
+            LinkMode::Single => {
+                package_actions.push(transform_file_to_action(
+                    link, src, dest, package, &variables,
+                )?);
+            }
+            LinkMode::Tree => {
+                // Mirror the directory and handle each file on its own
+                for file in list_tree_files(&pathbuf![&package.path, &src])? {
+                    let file = file.to_str().unwrap();
+                    package_actions.push(transform_file_to_action(
+                        link,
+                        format!("{}/{}", src.trim_end_matches('/'), file),
+                        format!("{}/{}", dest.trim_end_matches('/'), file),
+                        package,
+                        &variables,
+                    )?);
+                }
+            }
+        }
     }
+    package_actions.extend(transform_script_to_action(
+        &package.post_links,
+        ScriptOperation::Run,
+        "post_links",
+        package,
+        context,
+    )?);
+    package_actions.extend(transform_script_to_action(
+        &package.pre_uninstall,
+        ScriptOperation::OnUninstall,
+        "pre_uninstall",
+        package,
+        context,
+    )?);
+    package_actions.extend(transform_script_to_action(
+        &package.post_uninstall,
+        ScriptOperation::OnUninstall,
+        "post_uninstall",
+        package,
+        context,
+    )?);
     return Ok(package_actions);
 }
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use crate::host::config::HostConfiguration;
+
+use super::SystemAction;
+
+pub fn action_package_path(sysaction: &SystemAction) -> &str {
+    let origin = match sysaction {
+        SystemAction::Package { origin, .. } => origin,
+        SystemAction::Script { origin, .. } => origin,
+        SystemAction::File { origin, .. } => origin,
+    };
+    return origin.split(':').next().unwrap();
+}
+
+/// True when package depends on dependency, directly or not
+fn depends_on(
+    package_path: &str,
+    dependency_path: &str,
+    dependencies: &HashMap<String, Vec<String>>,
+) -> bool {
+    return dependencies.get(package_path).is_some_and(|paths| {
+        paths
+            .iter()
+            .any(|path| path == dependency_path || depends_on(path, dependency_path, dependencies))
+    });
+}
+
+/// True when action has to wait for the earlier action to be done
+fn must_wait(
+    action: &SystemAction,
+    earlier: &SystemAction,
+    dependencies: &HashMap<String, Vec<String>>,
+) -> bool {
+    let package_path = action_package_path(action);
+    let earlier_package_path = action_package_path(earlier);
+    // Actions of a package run in order, after the packages it depends on,
+    // and a dot package after the pre_install of the package depending on it
+    if package_path == earlier_package_path
+        || depends_on(package_path, earlier_package_path, dependencies)
+        || depends_on(earlier_package_path, package_path, dependencies)
+    {
+        return true;
+    }
+    return match (action, earlier) {
+        (
+            SystemAction::File { dest, .. },
+            SystemAction::File {
+                dest: earlier_dest, ..
+            },
+        ) => dest == earlier_dest,
+        // A package moved from a dot package to another one
+        (
+            SystemAction::Package { source, name, .. },
+            SystemAction::Package {
+                source: earlier_source,
+                name: earlier_name,
+                ..
+            },
+        ) => {
+            source == earlier_source
+                && name
+                    .split(' ')
+                    .any(|item| earlier_name.split(' ').any(|earlier| earlier == item))
+        }
+        _ => false,
+    };
+}
+
+/// Pick which actions can run at the same time, in the order they are given
+pub struct Scheduler {
+    /// Indexes of the earlier actions each action waits for
+    waits_for: Vec<Vec<usize>>,
+    /// Package manager of each package action, only one command at once per package manager
+    sources: Vec<Option<String>>,
+    /// Actions attached to the terminal, run alone
+    interactive: Vec<bool>,
+    started: Vec<bool>,
+    finished: Vec<bool>,
+    busy_sources: Vec<String>,
+    running: usize,
+    running_interactive: bool,
+}
+
+impl Scheduler {
+    pub fn new(
+        actions: &Vec<SystemAction>,
+        config: &HostConfiguration,
+        dependencies: &HashMap<String, Vec<String>>,
+    ) -> Scheduler {
+        let waits_for = actions
+            .iter()
+            .enumerate()
+            .map(|(index, action)| {
+                (0..index)
+                    .filter(|earlier| must_wait(action, &actions[*earlier], dependencies))
+                    .collect()
+            })
+            .collect();
+        let sources = actions
+            .iter()
+            .map(|action| match action {
+                SystemAction::Package { source, .. } => Some(source.clone()),
+                _ => None,
+            })
+            .collect();
+        let interactive = actions
+            .iter()
+            .map(|action| match action {
+                SystemAction::Package { source, .. } => config
+                    .package_managers
+                    .get(source)
+                    .is_some_and(|pm| pm.interactive),
+                SystemAction::Script { interactive, .. } => *interactive,
+                SystemAction::File { .. } => false,
+            })
+            .collect();
+        return Scheduler {
+            waits_for,
+            sources,
+            interactive,
+            started: vec![false; actions.len()],
+            finished: vec![false; actions.len()],
+            busy_sources: vec![],
+            running: 0,
+            running_interactive: false,
+        };
+    }
+
+    /// Indexes of the actions not started yet
+    pub fn pending(&self) -> Vec<usize> {
+        return (0..self.started.len())
+            .filter(|index| !self.started[*index])
+            .collect();
+    }
+
+    pub fn is_done(&self) -> bool {
+        return self.started.iter().all(|started| *started);
+    }
+
+    fn is_ready(&self, index: usize) -> bool {
+        if self.started[index] || self.running_interactive {
+            return false;
+        }
+        if self.interactive[index] && self.running > 0 {
+            return false;
+        }
+        if let Some(source) = &self.sources[index] {
+            if self.busy_sources.contains(source) {
+                return false;
+            }
+        }
+        return self.waits_for[index]
+            .iter()
+            .all(|earlier| self.finished[*earlier]);
+    }
+
+    /// Start the first action which can run now
+    pub fn start_next(&mut self) -> Option<usize> {
+        let index = (0..self.started.len()).find(|index| self.is_ready(*index))?;
+        self.started[index] = true;
+        self.running += 1;
+        self.running_interactive = self.interactive[index];
+        if let Some(source) = &self.sources[index] {
+            self.busy_sources.push(source.clone());
+        }
+        return Some(index);
+    }
+
+    pub fn finish(&mut self, index: usize) {
+        self.finished[index] = true;
+        self.running -= 1;
+        if self.interactive[index] {
+            self.running_interactive = false;
+        }
+        if let Some(source) = &self.sources[index] {
+            self.busy_sources.retain(|busy| busy != source);
+        }
+    }
+
+    /// Mark an action as done without running it
+    pub fn skip(&mut self, index: usize) {
+        self.started[index] = true;
+        self.finished[index] = true;
+    }
+}
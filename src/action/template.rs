@@ -0,0 +1,44 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+use crate::variables::Variables;
+
+fn lookup(name: &str, variables: &Variables) -> Option<String> {
+    if let Some(env_name) = name.strip_prefix("env.") {
+        return std::env::var(env_name).ok();
+    }
+    return variables.get(name).cloned();
+}
+
+/// Replace every `{{ name }}` placeholder by its value,
+/// `env.NAME` placeholders are read from the environment
+pub fn render(content: &str, variables: &Variables) -> Result<String> {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => bail!("Unclosed placeholder \"{}\"", &rest[start..]),
+        };
+        let name = rest[start + 2..end].trim();
+        match lookup(name, variables) {
+            Some(value) => output.push_str(&value),
+            None => bail!("Unknown template variable \"{}\"", name),
+        }
+        rest = &rest[end + 2..];
+    }
+    output.push_str(rest);
+    return Ok(output);
+}
+
+pub fn render_file(path: &Path, variables: &Variables) -> Result<String> {
+    let content = std::fs::read_to_string(path).context(format!(
+        "Unable to read template {}",
+        path.to_string_lossy()
+    ))?;
+    return render(&content, variables).context(format!(
+        "Unable to render template {}",
+        path.to_string_lossy()
+    ));
+}
@@ -1,9 +1,14 @@
 use crate::{
+    backup::BackupStore,
     hashmap,
-    host::config::{PackageManager, PackageManagerCommands},
+    host::config::{EscalationTool, PackageManager, PackageManagerCommands},
 };
 
-use super::*;
+use super::{
+    execution::{execute, ExecutionOptions, Outcome, OutputMode},
+    *,
+};
+use std::collections::{BTreeMap, HashMap};
 
 #[test]
 fn test_compacter_adjoining_packages() {
@@ -61,26 +66,39 @@ fn test_compacter_adjoining_packages() {
             src: "zshrc".to_string(),
             dest: "~/.zshrc".to_string(),
             origin: "packages/zsh".to_string(),
+            checksum: None,
+            backup: None,
+            escalate: false,
         },
         SystemAction::File {
             operation: FileOperation::Copy,
             src: "profile".to_string(),
             dest: "~/.$USER.profile".to_string(),
             origin: "packages/zsh".to_string(),
+            checksum: None,
+            backup: None,
+            escalate: false,
         },
     ];
 
     let config = HostConfiguration {
+        extends: vec![],
+        escalation: EscalationTool::Sudo,
         package_managers: hashmap![
             "os".to_string() => PackageManager {
                 multiple: true,
+                interactive: false,
+                escalate: false,
                 commands: PackageManagerCommands {
                     install: "paru -S <package>".to_string(),
                     uninstall: "paru -Runs <package>".to_string(),
                     clean: None,
+                    query: None,
+                    list_installed: None,
                 },
             }
         ],
+        vars: HashMap::new(),
     };
 
     let result = vec![
@@ -107,14 +125,390 @@ fn test_compacter_adjoining_packages() {
                 src: "zshrc".to_string(),
                 dest: "~/.zshrc".to_string(),
                 origin: "packages/zsh".to_string(),
+                checksum: None,
+                backup: None,
+                escalate: false,
             },
             SystemAction::File {
                 operation: FileOperation::Copy,
                 src: "profile".to_string(),
                 dest: "~/.$USER.profile".to_string(),
                 origin: "packages/zsh".to_string(),
+                checksum: None,
+                backup: None,
+                escalate: false,
             },
         ];
     let merged_actions = compact_mergeable_actions(&sysactions, &config);
     assert_eq!(result, merged_actions);
 }
+
+#[test]
+fn test_scheduler_ordering() {
+    let package = |source: &str, name: &str, origin: &str| SystemAction::Package {
+        operation: PackageOperation::Install,
+        source: source.to_string(),
+        name: name.to_string(),
+        origin: origin.to_string(),
+    };
+    let link = |dest: &str, origin: &str| SystemAction::File {
+        operation: FileOperation::Link,
+        src: "config".to_string(),
+        dest: dest.to_string(),
+        origin: origin.to_string(),
+        checksum: None,
+        backup: None,
+        escalate: false,
+    };
+    // The dot packages come between the pre_install and the packages of the host
+    let sysactions = vec![
+        SystemAction::Script {
+            operation: ScriptOperation::Run,
+            script: "echo start".to_string(),
+            origin: "hosts/desktop:pre_install".to_string(),
+            policy: RunPolicy::OnChange,
+            checksum: None,
+            interactive: false,
+            escalate: false,
+        },
+        package("os", "git", "packages/git"),
+        link("~/.gitconfig", "packages/git"),
+        package("os", "tmux", "packages/tmux"),
+        link("~/.tmux.conf", "packages/tmux"),
+        package("flatpak", "firefox", "hosts/desktop"),
+    ];
+    let config = HostConfiguration {
+        extends: vec![],
+        escalation: EscalationTool::Sudo,
+        package_managers: HashMap::new(),
+        vars: HashMap::new(),
+    };
+    let dependencies = hashmap! {
+        "hosts/desktop".to_string() => vec!["packages/git".to_string(), "packages/tmux".to_string()]
+    };
+    let mut scheduler = scheduler::Scheduler::new(&sysactions, &config, &dependencies);
+    // The dot packages wait for the pre_install of the host
+    assert_eq!(Some(0), scheduler.start_next());
+    assert_eq!(None, scheduler.start_next());
+    scheduler.finish(0);
+    // os is busy, the links wait for their package and the host for its dependencies
+    assert_eq!(Some(1), scheduler.start_next());
+    assert_eq!(None, scheduler.start_next());
+    scheduler.finish(1);
+    assert_eq!(Some(2), scheduler.start_next());
+    assert_eq!(Some(3), scheduler.start_next());
+    assert_eq!(None, scheduler.start_next());
+    scheduler.finish(3);
+    assert_eq!(Some(4), scheduler.start_next());
+    scheduler.finish(2);
+    assert_eq!(None, scheduler.start_next());
+    scheduler.finish(4);
+    assert_eq!(Some(5), scheduler.start_next());
+    scheduler.finish(5);
+    assert!(scheduler.is_done());
+}
+
+#[test]
+fn test_alternate_root() {
+    let sysactions = vec![
+        SystemAction::Package {
+            operation: PackageOperation::Install,
+            source: "os".to_string(),
+            name: "git".to_string(),
+            origin: "packages/git".to_string(),
+        },
+        SystemAction::File {
+            operation: FileOperation::Copy,
+            src: "gitconfig".to_string(),
+            dest: "/home/user/.gitconfig".to_string(),
+            origin: "packages/git".to_string(),
+            checksum: None,
+            backup: None,
+            escalate: false,
+        },
+    ];
+    let rooted = prefix_root(sysactions.clone(), Path::new("/mnt/image"));
+    assert_eq!(sysactions[0], rooted[0]);
+    assert!(matches!(
+        &rooted[1],
+        SystemAction::File { dest, .. } if dest == "/mnt/image/home/user/.gitconfig"
+    ));
+
+    let mut config: HostConfiguration = serde_yaml::from_str(
+        "package_managers:\n  os:\n    multiple: true\n    commands:\n      install: pacman --root <root> -S <package>\n      uninstall: pacman -R <package>\n",
+    )
+    .unwrap();
+    config.set_root("/mnt/image");
+    assert_eq!(
+        "pacman --root /mnt/image -S <package>",
+        config.package_managers["os"].commands.install
+    );
+}
+
+#[test]
+fn test_backup_restored_on_removal() {
+    let directory =
+        std::env::temp_dir().join(format!("dotstrap-test-backup-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let dest = directory.join("home").join(".rc");
+    std::fs::create_dir_all(dest.parent().unwrap()).unwrap();
+    std::fs::write(&dest, "mine").unwrap();
+    let mut store = BackupStore {
+        root: directory.join("backups"),
+        created: BTreeMap::new(),
+    };
+
+    let backup = store.backup(&dest).unwrap();
+    assert!(!dest.exists());
+    assert_eq!("mine", std::fs::read_to_string(&backup).unwrap());
+    assert_eq!(Some(dest.clone()), store.original_path(&backup));
+    assert_eq!(vec![backup.clone()], store.list().unwrap());
+
+    // The link which replaced the file is removed, the file comes back
+    std::fs::write(&dest, "linked").unwrap();
+    let removal = SystemAction::File {
+        operation: FileOperation::Remove,
+        src: String::new(),
+        dest: dest.to_string_lossy().to_string(),
+        origin: "packages/base".to_string(),
+        checksum: None,
+        backup: Some(backup.to_string_lossy().to_string()),
+        escalate: false,
+    };
+    let config: HostConfiguration = serde_yaml::from_str("{}").unwrap();
+    let report = execute(
+        &vec![removal],
+        &config,
+        &VariableContext::default(),
+        &mut store,
+        &ExecutionOptions {
+            dry_run: false,
+            output: OutputMode::Quiet,
+            keep_going: false,
+            dependencies: HashMap::new(),
+            jobs: 1,
+            written: BTreeMap::new(),
+        },
+    );
+    assert_eq!(
+        1,
+        report.count(|outcome| matches!(outcome, Outcome::Succeeded))
+    );
+    assert_eq!("mine", std::fs::read_to_string(&dest).unwrap());
+    assert!(store.list().unwrap().is_empty());
+
+    // The backups referenced by the lockfile are kept
+    let backup = store.backup(&dest).unwrap();
+    assert!(store.prune(&vec![backup.clone()]).unwrap().is_empty());
+    assert_eq!(vec![backup.clone()], store.prune(&vec![]).unwrap());
+    assert!(!backup.exists());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_bundled_script() {
+    let directory =
+        std::env::temp_dir().join(format!("dotstrap-test-bundle-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let origin = directory.join("packages").join("editor");
+    std::fs::create_dir_all(origin.join("nvim").join("lua")).unwrap();
+    std::fs::write(origin.join("vimrc"), "set number\n").unwrap();
+    std::fs::write(origin.join("nvim").join("init.lua"), "require('plugins')\n").unwrap();
+    std::fs::write(
+        origin.join("nvim").join("lua").join("plugins.lua"),
+        "return {}\n",
+    )
+    .unwrap();
+    let home = directory.join("home");
+    let file = |operation: FileOperation, src: &str, dest: &str| SystemAction::File {
+        operation,
+        src: src.to_string(),
+        dest: home.join(dest).to_string_lossy().to_string(),
+        origin: origin.to_string_lossy().to_string(),
+        checksum: None,
+        backup: None,
+        escalate: false,
+    };
+    let sysactions = vec![
+        file(FileOperation::Link, "vimrc", ".vimrc"),
+        file(FileOperation::Copy, "vimrc", ".vimrc.example"),
+        file(FileOperation::Link, "nvim", ".config/nvim"),
+    ];
+    let config: HostConfiguration = serde_yaml::from_str("{}").unwrap();
+    let script =
+        generation::generate_shell_script(&sysactions, &config, &VariableContext::default(), true)
+            .unwrap()
+            .join("\n");
+    // Each source is embedded once, the directory with every file under it
+    assert_eq!(1, script.matches("vimrc\" <<'DOTSTRAP_EOF'").count());
+    assert!(script.contains("plugins.lua\" <<'DOTSTRAP_EOF'\ncmV0dXJuIHt9Cg==\nDOTSTRAP_EOF"));
+
+    // The script works once the repository is gone
+    std::fs::remove_dir_all(&origin).unwrap();
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(&script)
+        .env("DOTSTRAP_BUNDLE_DIR", directory.join("bundle"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        "set number\n",
+        std::fs::read_to_string(home.join(".vimrc")).unwrap()
+    );
+    assert_eq!(
+        "set number\n",
+        std::fs::read_to_string(home.join(".vimrc.example")).unwrap()
+    );
+    assert_eq!(
+        "return {}\n",
+        std::fs::read_to_string(home.join(".config/nvim/lua/plugins.lua")).unwrap()
+    );
+    assert!(home.join(".config/nvim").is_symlink());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_dot_packages_run_inside_their_dependent() {
+    let package = |path: &str, content: &str| {
+        let mut definition: PackageDefinition = serde_yaml::from_str(content).unwrap();
+        definition.name = path.rsplit('/').next().unwrap().to_string();
+        definition.path = path.to_string();
+        return definition;
+    };
+    let host = package(
+        "hosts/desktop",
+        "pre_install: echo host\ndependencies: [dot:base, curl, dot:shell]\npost_install: echo host",
+    );
+    let graph = crate::resolver::resolve_with(&host, |name| {
+        return Ok(match name {
+            "base" => package(
+                "packages/base",
+                "pre_install: echo base\ndependencies: [git, dot:shell]\npost_install: echo base",
+            ),
+            _ => package("packages/shell", "dependencies: [zsh]"),
+        });
+    })
+    .unwrap();
+    let sysactions = transform_host_to_actions(&host, &graph, &VariableContext::default()).unwrap();
+    let origins: Vec<&str> = sysactions
+        .iter()
+        .map(|sysaction| match sysaction {
+            SystemAction::Package { origin, .. } => origin.as_str(),
+            SystemAction::Script { origin, .. } => origin.as_str(),
+            SystemAction::File { origin, .. } => origin.as_str(),
+        })
+        .collect();
+    // A dot package is installed once, where it is first needed
+    assert_eq!(
+        vec![
+            "hosts/desktop:pre_install",
+            "packages/base:pre_install",
+            "packages/base",
+            "packages/shell",
+            "packages/base:post_install",
+            "hosts/desktop",
+            "hosts/desktop:post_install",
+        ],
+        origins
+    );
+}
+
+#[test]
+fn test_template_rendering() {
+    let variables = hashmap![
+        "name".to_string() => "Kelgors".to_string(),
+        "hostname".to_string() => "desktop".to_string()
+    ];
+    assert_eq!(
+        "Hi Kelgors on desktop!",
+        template::render("Hi {{ name }} on {{hostname}}!", &variables).unwrap()
+    );
+    std::env::set_var("DOTSTRAP_TEST_TEMPLATE", "from-env");
+    assert_eq!(
+        "value=from-env",
+        template::render("value={{ env.DOTSTRAP_TEST_TEMPLATE }}", &variables).unwrap()
+    );
+    assert_eq!(
+        "Unknown template variable \"email\"",
+        template::render("{{ email }}", &variables)
+            .unwrap_err()
+            .to_string()
+    );
+    assert!(template::render("{{ env.DOTSTRAP_TEST_UNDEFINED }}", &variables).is_err());
+    assert!(template::render("{{ name", &variables).is_err());
+}
+
+#[test]
+fn test_template_rerendered_on_change() {
+    let directory =
+        std::env::temp_dir().join(format!("dotstrap-test-template-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("gitconfig"), "email = {{ email }}\n").unwrap();
+    let dest = directory.join("home").join(".gitconfig");
+    let mut package: PackageDefinition = serde_yaml::from_str(&format!(
+        "links:\n  - src: gitconfig\n    dest: {}\n    template: true",
+        dest.to_string_lossy()
+    ))
+    .unwrap();
+    package.path = directory.to_string_lossy().to_string();
+    let actions = |email: &str| {
+        let context = VariableContext::new(
+            HashMap::new(),
+            hashmap!["email".to_string() => email.to_string()],
+            &package,
+            &HashMap::new(),
+        );
+        return transform_host_to_actions(&package, &Default::default(), &context).unwrap();
+    };
+
+    // The checksum of the rendered content is what the lockfile compares
+    let first = actions("me@home.com");
+    assert_eq!(first, actions("me@home.com"));
+    let second = actions("me@work.com");
+    assert_ne!(first, second);
+    assert!(matches!(
+        &second[0],
+        SystemAction::File { operation: FileOperation::Template, checksum: Some(checksum), .. }
+            if *checksum == crate::helpers::checksum(b"email = me@work.com\n")
+    ));
+
+    let config: HostConfiguration = serde_yaml::from_str("{}").unwrap();
+    let context = VariableContext::new(
+        HashMap::new(),
+        hashmap!["email".to_string() => "me@work.com".to_string()],
+        &package,
+        &HashMap::new(),
+    );
+    let mut store = BackupStore {
+        root: directory.join("backups"),
+        created: BTreeMap::new(),
+    };
+    let options = ExecutionOptions {
+        dry_run: false,
+        output: OutputMode::Quiet,
+        keep_going: false,
+        dependencies: HashMap::new(),
+        jobs: 1,
+        written: BTreeMap::new(),
+    };
+    execute(&second, &config, &context, &mut store, &options);
+    assert_eq!(
+        "email = me@work.com\n",
+        std::fs::read_to_string(&dest).unwrap()
+    );
+    // Removing the entry deletes the rendered file
+    let removal = SystemAction::File {
+        operation: FileOperation::Remove,
+        src: String::new(),
+        dest: dest.to_string_lossy().to_string(),
+        origin: package.path.clone(),
+        checksum: None,
+        backup: None,
+        escalate: false,
+    };
+    execute(&vec![removal], &config, &context, &mut store, &options);
+    assert!(!dest.exists());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
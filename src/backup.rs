@@ -0,0 +1,153 @@
+use anyhow::{bail, Context, Result};
+use pathbuf::pathbuf;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Files moved away by dotstrap before being replaced by a link,
+/// stored under $XDG_STATE_HOME/dotstrap/backups/<hostname>/<original path>.<timestamp>
+pub struct BackupStore {
+    pub root: PathBuf,
+    /// Backups made during this run, by destination
+    pub created: BTreeMap<String, String>,
+}
+
+fn state_dir() -> Result<PathBuf> {
+    if let Ok(state_home) = std::env::var("XDG_STATE_HOME") {
+        if !state_home.is_empty() {
+            return Ok(PathBuf::from(state_home));
+        }
+    }
+    let home = std::env::var("HOME").context("Unable to find the HOME directory")?;
+    return Ok(pathbuf![&home, ".local", "state"]);
+}
+
+/// Move a file or a directory, falling back to copy & remove across filesystems
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if from.is_dir() {
+        bail!(
+            "Unable to move directory {} to {}",
+            from.to_string_lossy(),
+            to.to_string_lossy()
+        );
+    }
+    fs::copy(from, to).context(format!(
+        "Unable to copy {} to {}",
+        from.to_string_lossy(),
+        to.to_string_lossy()
+    ))?;
+    fs::remove_file(from)?;
+    return Ok(());
+}
+
+impl BackupStore {
+    pub fn new(hostname: &str) -> Result<BackupStore> {
+        return Ok(BackupStore {
+            root: pathbuf![&state_dir()?, "dotstrap", "backups", hostname],
+            created: BTreeMap::new(),
+        });
+    }
+
+    /// Where the file at dest is saved when backed up now
+    pub fn backup_path(&self, dest: &Path) -> Result<PathBuf> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let relative_dest = dest.strip_prefix("/").unwrap_or(dest);
+        return Ok(PathBuf::from(format!(
+            "{}.{}",
+            self.root.join(relative_dest).to_string_lossy(),
+            timestamp
+        )));
+    }
+
+    /// Remember a backup made during this run
+    pub fn record(&mut self, dest: &Path, backup_path: &Path) {
+        self.created.insert(
+            dest.to_string_lossy().to_string(),
+            backup_path.to_string_lossy().to_string(),
+        );
+    }
+
+    /// Move the file at dest into the store and return where it has been saved
+    pub fn backup(&mut self, dest: &Path) -> Result<PathBuf> {
+        let backup_path = self.backup_path(dest)?;
+        move_path(dest, &backup_path)?;
+        self.record(dest, &backup_path);
+        return Ok(backup_path);
+    }
+
+    /// Put back a saved file at its original place
+    pub fn restore(backup: &Path, dest: &Path) -> Result<()> {
+        if !backup.exists() {
+            bail!("Backup {} does not exist", backup.to_string_lossy());
+        }
+        if dest.symlink_metadata().is_ok() {
+            if dest.is_dir() && !dest.is_symlink() {
+                fs::remove_dir_all(dest)?;
+            } else {
+                fs::remove_file(dest)?;
+            }
+        }
+        return move_path(backup, dest);
+    }
+
+    /// List every backup file of the store
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        let mut backups = vec![];
+        if !self.root.exists() {
+            return Ok(backups);
+        }
+        let mut pending = vec![self.root.clone()];
+        while let Some(directory) = pending.pop() {
+            for entry in fs::read_dir(&directory)? {
+                let entry = entry?;
+                let path = entry.path();
+                // A backed up directory ends with its timestamp
+                let is_backup = path
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().parse::<u64>().is_ok())
+                    .unwrap_or(false);
+                if entry.file_type()?.is_dir() && !is_backup {
+                    pending.push(path);
+                } else {
+                    backups.push(path);
+                }
+            }
+        }
+        backups.sort();
+        return Ok(backups);
+    }
+
+    /// Delete the backups of the store except the kept ones, return the deleted ones
+    pub fn prune(&self, keep: &Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        let mut deleted = vec![];
+        for backup in self.list()? {
+            if keep.contains(&backup) {
+                continue;
+            }
+            if backup.is_dir() {
+                fs::remove_dir_all(&backup)?;
+            } else {
+                fs::remove_file(&backup)?;
+            }
+            deleted.push(backup);
+        }
+        return Ok(deleted);
+    }
+
+    /// Original path of a backup from the store
+    pub fn original_path(&self, backup: &Path) -> Option<PathBuf> {
+        let relative = backup.strip_prefix(&self.root).ok()?;
+        let relative = relative.to_string_lossy();
+        let (original, _timestamp) = relative.rsplit_once('.')?;
+        return Some(pathbuf!["/", original]);
+    }
+}
@@ -40,6 +40,21 @@ pub enum Action {
         #[arg(short, long, default_value_t = false)]
         push: bool,
     },
+    /// Check a host configuration and the packages it depends on
+    Validate {
+        /// Override hostname, load specific hosts/<hostname/package.yml
+        #[arg()]
+        hostname: Option<String>,
+    },
+    /// Compare your system with the last installation, fails on differences
+    Status {
+        /// Override hostname, load specific hosts/<hostname/package.yml
+        #[arg()]
+        hostname: Option<String>,
+        /// Root directory of the installation, <root> in package manager commands
+        #[arg(long)]
+        root: Option<String>,
+    },
     /// Generate a shell script from your configuration
     Generate {
         /// Override hostname, load specific hosts/<hostname/package.yml
@@ -48,6 +63,21 @@ pub enum Action {
         /// (Re)Install all packages
         #[arg(short, long, default_value_t = false)]
         full: bool,
+        /// Root directory of the installation, <root> in package manager commands
+        #[arg(long)]
+        root: Option<String>,
+        /// Only install the packages
+        #[arg(long, default_value_t = false, conflicts_with = "user")]
+        system: bool,
+        /// Only install the dotfiles
+        #[arg(long, default_value_t = false)]
+        user: bool,
+        /// Skip a package manager, or the dot, link or script actions
+        #[arg(long, value_delimiter = ',')]
+        skip: Vec<String>,
+        /// Embed the linked and copied files, the script runs without the repository
+        #[arg(long, default_value_t = false)]
+        bundle: bool,
     },
     /// Apply your hostname configuration
     Install {
@@ -66,5 +96,118 @@ pub enum Action {
         /// generate lockfile even in dry mode
         #[arg(short, long, default_value_t = false)]
         lock: bool,
+
+        /// Keep running the actions which do not depend on a failed package
+        #[arg(short, long, default_value_t = false)]
+        keep_going: bool,
+
+        /// On failure, undo what has been applied to get back to the previous installation
+        #[arg(short, long, default_value_t = false, conflicts_with = "keep_going")]
+        atomic: bool,
+
+        /// Only show the output of the commands which fail
+        #[arg(short, long, default_value_t = false)]
+        quiet: bool,
+
+        /// How many independent actions can run at the same time
+        #[arg(short, long, default_value_t = 1, value_parser = value_parser!(u16).range(1..))]
+        jobs: u16,
+
+        /// Clone or update a dotstrap repository, from a URL or a path, and install from it
+        #[arg(long)]
+        repo: Option<String>,
+
+        /// Root directory of the installation, <root> in package manager commands
+        #[arg(long)]
+        root: Option<String>,
+        /// Only install the packages
+        #[arg(long, default_value_t = false, conflicts_with = "user")]
+        system: bool,
+        /// Only install the dotfiles
+        #[arg(long, default_value_t = false)]
+        user: bool,
+        /// Skip a package manager, or the dot, link or script actions
+        #[arg(long, value_delimiter = ',')]
+        skip: Vec<String>,
+    },
+    /// Pull the repository, show the incoming commits and changes, then install
+    Sync {
+        /// Override hostname, load specific hosts/<hostname/package.yml
+        #[arg()]
+        hostname: Option<String>,
+
+        /// Pull without performing actions on your system
+        #[arg(short, long, default_value_t = false)]
+        dry: bool,
+
+        /// (Re)Install all packages
+        #[arg(short, long, default_value_t = false)]
+        full: bool,
+
+        /// generate lockfile even in dry mode
+        #[arg(short, long, default_value_t = false)]
+        lock: bool,
+
+        /// Keep running the actions which do not depend on a failed package
+        #[arg(short, long, default_value_t = false)]
+        keep_going: bool,
+
+        /// On failure, undo what has been applied to get back to the previous installation
+        #[arg(short, long, default_value_t = false, conflicts_with = "keep_going")]
+        atomic: bool,
+
+        /// Only show the output of the commands which fail
+        #[arg(short, long, default_value_t = false)]
+        quiet: bool,
+
+        /// How many independent actions can run at the same time
+        #[arg(short, long, default_value_t = 1, value_parser = value_parser!(u16).range(1..))]
+        jobs: u16,
+
+        /// Root directory of the installation, <root> in package manager commands
+        #[arg(long)]
+        root: Option<String>,
+        /// Only install the packages
+        #[arg(long, default_value_t = false, conflicts_with = "user")]
+        system: bool,
+        /// Only install the dotfiles
+        #[arg(long, default_value_t = false)]
+        user: bool,
+        /// Skip a package manager, or the dot, link or script actions
+        #[arg(long, value_delimiter = ',')]
+        skip: Vec<String>,
+    },
+    /// Manage the files saved before being replaced by links
+    Backups {
+        #[command(subcommand)]
+        action: BackupsAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BackupsAction {
+    /// List the saved files
+    List {
+        /// Override hostname
+        #[arg()]
+        hostname: Option<String>,
+    },
+    /// Put back a saved file at its original place
+    Restore {
+        /// Original path of the file
+        #[arg()]
+        dest: String,
+        /// Override hostname
+        #[arg()]
+        hostname: Option<String>,
+    },
+    /// Delete the saved files not referenced by the lockfile
+    Prune {
+        /// Override hostname
+        #[arg()]
+        hostname: Option<String>,
+        /// Delete every saved file
+        #[arg(short, long, default_value_t = false)]
+        all: bool,
     },
 }
@@ -3,6 +3,7 @@ use pathbuf::pathbuf;
 use std::str::FromStr;
 
 use super::install::{run_install, RunInstallOptions};
+use crate::action::filter::ActionFilter;
 use crate::package::{DependencyDefinition, PackageDefinition};
 
 #[cfg(feature = "git")]
@@ -43,6 +44,14 @@ pub fn run_add(hostname: String, options: RunAddOptions) -> Result<()> {
                 dry: false,
                 full: false,
                 lock: false,
+                keep_going: false,
+                atomic: false,
+                quiet: false,
+                jobs: 1,
+                repo: None,
+                root: None,
+                filter: ActionFilter::default(),
+                show_plan: false,
             },
         )?;
     }
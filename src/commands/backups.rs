@@ -0,0 +1,105 @@
+use anyhow::{bail, Result};
+use colored::Colorize;
+use pathbuf::pathbuf;
+use std::path::PathBuf;
+
+use crate::{
+    action::SystemAction,
+    backup::BackupStore,
+    lockfile::{read_lockfile, write_lockfile},
+};
+
+pub enum BackupsAction {
+    List,
+    Restore { dest: String },
+    Prune { all: bool },
+}
+
+pub struct RunBackupsOptions {
+    pub action: BackupsAction,
+}
+
+fn referenced_backups(system_actions: &Vec<SystemAction>) -> Vec<PathBuf> {
+    return system_actions
+        .iter()
+        .filter_map(|sysaction| match sysaction {
+            SystemAction::File {
+                backup: Some(backup),
+                ..
+            } => Some(pathbuf![backup]),
+            _ => None,
+        })
+        .collect();
+}
+
+pub fn run_backups(hostname: String, options: RunBackupsOptions) -> Result<()> {
+    let store = BackupStore::new(&hostname)?;
+    let mut lockfile = read_lockfile(None)?;
+    let referenced = lockfile
+        .as_ref()
+        .map(|lockfile| referenced_backups(&lockfile.actions))
+        .unwrap_or_default();
+    match options.action {
+        BackupsAction::List => {
+            for backup in store.list()? {
+                let original = store.original_path(&backup).unwrap_or_default();
+                let state = if referenced.contains(&backup) {
+                    "in use".green()
+                } else {
+                    "unused".yellow()
+                };
+                println!(
+                    "[{}] {} {}",
+                    state,
+                    original.to_string_lossy(),
+                    backup.to_string_lossy()
+                );
+            }
+        }
+        BackupsAction::Restore { dest } => {
+            let dest_path = pathbuf![&shellexpand::tilde(&dest).to_string()];
+            // Prefer the backup recorded in the lockfile, then the latest one
+            let backup = referenced
+                .iter()
+                .find(|backup| store.original_path(backup).as_ref() == Some(&dest_path))
+                .cloned()
+                .or_else(|| {
+                    store
+                        .list()
+                        .ok()?
+                        .into_iter()
+                        .rev()
+                        .find(|backup| store.original_path(backup).as_ref() == Some(&dest_path))
+                });
+            let backup = match backup {
+                Some(backup) => backup,
+                None => bail!("No backup found for {}", dest_path.to_string_lossy()),
+            };
+            BackupStore::restore(&backup, &dest_path)?;
+            println!(
+                "[{}] {} {}",
+                "RESTORE".green(),
+                backup.to_string_lossy(),
+                dest_path.to_string_lossy()
+            );
+            // The lockfile should not restore it a second time
+            if let Some(lockfile) = lockfile.as_mut() {
+                for sysaction in lockfile.actions.iter_mut() {
+                    if let SystemAction::File { backup: saved, .. } = sysaction {
+                        if saved.as_deref() == Some(&backup.to_string_lossy()) {
+                            *saved = None;
+                        }
+                    }
+                }
+                write_lockfile(lockfile, None)?;
+            }
+        }
+        BackupsAction::Prune { all } => {
+            let keep = if all { vec![] } else { referenced };
+            for backup in store.prune(&keep)? {
+                println!("[{}] {}", "DELETE".bright_red(), backup.to_string_lossy());
+            }
+        }
+    }
+    return Ok(());
+}
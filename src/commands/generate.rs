@@ -3,29 +3,80 @@ use pathbuf::pathbuf;
 
 use crate::{
     action::{
-        compact_mergeable_actions, generation::generate_shell_script, transform_package_to_actions,
-        SystemAction,
+        compact_mergeable_actions, filter::ActionFilter, generation::generate_shell_script,
+        prefix_root, transform_host_to_actions, SystemAction,
+    },
+    host::{facts::gather_facts, HostDefinition},
+    lockfile::{
+        build_action_diff, filter_planned_actions, read_lockfile, skip_preinstalled_packages,
     },
-    host::HostDefinition,
-    lockfile::build_action_diff,
     resolver,
+    variables::VariableContext,
 };
 
 pub struct RunGenerateOptions {
     pub full: bool,
+    /// Directory the files are installed into, instead of /
+    pub root: Option<String>,
+    pub filter: ActionFilter,
+    /// Embed the linked and copied files in the script
+    pub bundle: bool,
 }
 
 pub fn run_generate(hostname: String, options: RunGenerateOptions) -> Result<()> {
-    let host_definition = HostDefinition::from_path(&pathbuf!["hosts", &hostname])?;
-    let packages_repo = resolver::resolve_dependencies(&host_definition.package)?;
+    let root = match &options.root {
+        Some(root) => Some(std::env::current_dir()?.join(root)),
+        None => None,
+    };
+    let mut host_definition = HostDefinition::from_path(&pathbuf!["hosts", &hostname])?;
+    host_definition.config.set_root(
+        &root
+            .as_ref()
+            .map_or("/".into(), |root| root.to_string_lossy()),
+    );
+    options.filter.check(&host_definition.config)?;
+    let graph = resolver::resolve_dependencies(&host_definition.package)?;
+    let context = VariableContext::new(
+        gather_facts(&hostname),
+        host_definition.vars.clone(),
+        &host_definition.package,
+        &graph.packages,
+    );
+    let mut next_system_actions =
+        transform_host_to_actions(&host_definition.package, &graph, &context)?;
+    if let Some(root) = &root {
+        next_system_actions = prefix_root(next_system_actions, root);
+    }
+    let previous_lockfile = read_lockfile(root.as_deref())?;
+    let previous_actions = previous_lockfile
+        .as_ref()
+        .map(|lockfile| lockfile.actions.clone())
+        .unwrap_or_default();
+    // the filtered out actions stay as the previous installation left them
     let next_system_actions =
-        transform_package_to_actions(&host_definition.package, &packages_repo, &mut vec![])?;
+        filter_planned_actions(&next_system_actions, &previous_actions, |sysaction| {
+            options.filter.accepts(sysaction)
+        });
     // merge next actions with cleaning actions
-    let all_actions: Vec<SystemAction> = build_action_diff(&next_system_actions, options.full)?;
+    let mut all_actions: Vec<SystemAction> =
+        build_action_diff(&next_system_actions, options.full, root.as_deref())?;
+    all_actions.retain(|sysaction| options.filter.accepts(sysaction));
+    // the script may run elsewhere, only the known preinstalled packages are kept
+    let (all_actions, _) = skip_preinstalled_packages(
+        all_actions,
+        &next_system_actions,
+        previous_lockfile.as_ref(),
+        |_, _| Ok(None),
+    )?;
     // compacting actions when possible
     let merged_actions = compact_mergeable_actions(&all_actions, &host_definition.config);
     // generate shell script
-    let script = generate_shell_script(&merged_actions, &host_definition.config)?;
+    let script = generate_shell_script(
+        &merged_actions,
+        &host_definition.config,
+        &context,
+        options.bundle,
+    )?;
     println!("{}", script.join("\n"));
     return Ok(());
 }
@@ -1,38 +1,212 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use colored::Colorize;
 use pathbuf::pathbuf;
 use promptly::prompt_default;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::{
     action::{
-        compact_mergeable_actions, execution::execute, transform_package_to_actions, SystemAction,
+        compact_mergeable_actions,
+        escalation::{check_not_escalated, escalate, needs_escalation},
+        execution::{describe, execute, ExecutionOptions, ExecutionReport, Outcome, OutputMode},
+        filter::ActionFilter,
+        installed::InstalledPackages,
+        prefix_root, transform_host_to_actions, FileOperation, PackageOperation, SystemAction,
+    },
+    backup::BackupStore,
+    host::{config::HostConfiguration, facts::gather_facts, HostDefinition},
+    lockfile::{
+        applied_actions, attach_backups, build_action_diff, filter_planned_actions, read_lockfile,
+        rollback_actions, skip_preinstalled_packages, write_lockfile, Lockfile,
     },
-    host::HostDefinition,
-    lockfile::build_action_diff,
     resolver,
+    variables::VariableContext,
 };
 
+#[cfg(feature = "git")]
+use crate::git;
+
 pub struct RunInstallOptions {
     pub dry: bool,
     pub full: bool,
     pub lock: bool,
+    pub keep_going: bool,
+    pub atomic: bool,
+    pub quiet: bool,
+    pub jobs: usize,
+    /// Repository to clone or update, and install from
+    pub repo: Option<String>,
+    /// Directory the files are installed into, instead of /
+    pub root: Option<String>,
+    pub filter: ActionFilter,
+    /// List the actions before asking to apply them
+    pub show_plan: bool,
+}
+
+/// Print the actions about to run, removals first as they come
+fn print_plan(sytem_actions: &Vec<SystemAction>) {
+    let rows: Vec<(String, String)> = sytem_actions.iter().map(describe).collect();
+    let operation_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    for (sysaction, (operation, target)) in sytem_actions.iter().zip(rows.iter()) {
+        let is_removal = matches!(
+            sysaction,
+            SystemAction::Package {
+                operation: PackageOperation::Uninstall,
+                ..
+            } | SystemAction::File {
+                operation: FileOperation::Remove,
+                ..
+            }
+        );
+        let sign = if is_removal { "-".red() } else { "+".green() };
+        println!(
+            "{} {:width$}  {}",
+            sign,
+            operation,
+            target,
+            width = operation_width
+        );
+    }
+}
+
+/// Undo the applied actions after a failure, returns the ones undone
+fn rollback(
+    report: &ExecutionReport,
+    previous_actions: &Vec<SystemAction>,
+    config: &HostConfiguration,
+    context: &VariableContext,
+    backups: &mut BackupStore,
+    output: OutputMode,
+) -> Vec<SystemAction> {
+    let applied = report.applied();
+    let (undone, undo_actions): (Vec<SystemAction>, Vec<SystemAction>) =
+        rollback_actions(&applied, previous_actions, &backups.created)
+            .into_iter()
+            .unzip();
+    println!("\nRolling back {} action(s)", undo_actions.len());
+    let rollback_report = execute(
+        &undo_actions,
+        config,
+        context,
+        backups,
+        &ExecutionOptions {
+            dry_run: false,
+            output,
+            keep_going: true,
+            dependencies: HashMap::new(),
+            jobs: 1,
+            written: BTreeMap::new(),
+        },
+    );
+    if !undo_actions.is_empty() {
+        rollback_report.print_summary();
+    }
+    // Scripts stay out of the lockfile to run again on the next installation
+    let mut rolled_back = vec![];
+    for sysaction in applied.iter() {
+        if let SystemAction::Script { origin, .. } = sysaction {
+            println!("[{}] {} cannot be rolled back", "WARNING".yellow(), origin);
+            rolled_back.push(sysaction.clone());
+        }
+    }
+    for (sysaction, (undo, outcome)) in undone.into_iter().zip(rollback_report.outcomes.iter()) {
+        if !matches!(outcome, Outcome::Succeeded) {
+            continue;
+        }
+        // The original file is back in place
+        if let SystemAction::File {
+            dest,
+            backup: Some(_),
+            ..
+        } = undo
+        {
+            backups.created.remove(dest);
+        }
+        rolled_back.push(sysaction);
+    }
+    return rolled_back;
+}
+
+/// Work from the clone of a remote repository
+#[cfg(feature = "git")]
+fn enter_repository(source: &str) -> Result<()> {
+    use anyhow::Context;
+    let path = git::checkout_repository(source)?;
+    std::env::set_current_dir(&path)
+        .context(format!("Unable to move into {}", path.to_string_lossy()))?;
+    return Ok(());
+}
+
+#[cfg(not(feature = "git"))]
+fn enter_repository(_: &str) -> Result<()> {
+    bail!("--repo needs dotstrap to be built with the git feature");
 }
 
 pub fn run_install(hostname: String, options: RunInstallOptions) -> Result<()> {
+    check_not_escalated()?;
     // Load host definition and prepare system actions from it
-    let host_definition = HostDefinition::from_path(&pathbuf!["hosts", &hostname])?;
-    let packages_repo = resolver::resolve_dependencies(&host_definition.package)?;
+    let root = match &options.root {
+        Some(root) => Some(std::env::current_dir()?.join(root)),
+        None => None,
+    };
+    if let Some(source) = &options.repo {
+        enter_repository(source)?;
+    }
+    let mut host_definition = HostDefinition::from_path(&pathbuf!["hosts", &hostname])?;
+    host_definition.config.set_root(
+        &root
+            .as_ref()
+            .map_or("/".into(), |root| root.to_string_lossy()),
+    );
+    options.filter.check(&host_definition.config)?;
+    let graph = resolver::resolve_dependencies(&host_definition.package)?;
+    let context = VariableContext::new(
+        gather_facts(&hostname),
+        host_definition.vars.clone(),
+        &host_definition.package,
+        &graph.packages,
+    );
+    let mut next_system_actions =
+        transform_host_to_actions(&host_definition.package, &graph, &context)?;
+    if let Some(root) = &root {
+        next_system_actions = prefix_root(next_system_actions, root);
+    }
+    // keep previous lockfile to carry over its backups and preinstalled packages
+    let previous_lockfile = read_lockfile(root.as_deref())?;
+    let previous_actions = previous_lockfile
+        .as_ref()
+        .map(|lockfile| lockfile.actions.clone())
+        .unwrap_or_default();
+    // the filtered out actions stay as the previous installation left them
     let next_system_actions =
-        transform_package_to_actions(&host_definition.package, &packages_repo, &mut vec![])?;
-    // save next lockfile before "cleaning" mutation
-    let serialized_next_lockfile: String = serde_yaml::to_string(&next_system_actions)?;
+        filter_planned_actions(&next_system_actions, &previous_actions, |sysaction| {
+            options.filter.accepts(sysaction)
+        });
     // merge next actions with cleaning actions
-    let all_actions: Vec<SystemAction> = build_action_diff(&next_system_actions, options.full)?;
+    let mut all_actions: Vec<SystemAction> =
+        build_action_diff(&next_system_actions, options.full, root.as_deref())?;
+    all_actions.retain(|sysaction| options.filter.accepts(sysaction));
+    // leave alone the packages dotstrap did not install
+    let mut installed = InstalledPackages::new(&host_definition.config);
+    let (all_actions, preinstalled) = skip_preinstalled_packages(
+        all_actions,
+        &next_system_actions,
+        previous_lockfile.as_ref(),
+        |source, name| installed.is_installed(source, name),
+    )?;
     // compacting actions when possible
     let merged_actions = compact_mergeable_actions(&all_actions, &host_definition.config);
 
     if options.dry {
         println!("DryMode: {}", options.dry);
     }
+    if options.show_plan {
+        if merged_actions.is_empty() {
+            println!("Nothing to do");
+            return Ok(());
+        }
+        print_plan(&merged_actions);
+    }
     let confirm_execution = if !options.dry {
         prompt_default(
             format!("Do you want to apply {} operations?", all_actions.len()),
@@ -43,12 +217,65 @@ pub fn run_install(hostname: String, options: RunInstallOptions) -> Result<()> {
     };
 
     if confirm_execution {
-        execute(&merged_actions, &host_definition.config, options.dry)?;
+        // Ask for the credentials once, they are kept until the end of the installation
+        let _keep_alive =
+            if !options.dry && needs_escalation(&merged_actions, &host_definition.config) {
+                Some(escalate(host_definition.config.escalation)?)
+            } else {
+                None
+            };
+        let mut backups = BackupStore::new(&hostname)?;
+        let output = if options.quiet {
+            OutputMode::Quiet
+        } else {
+            OutputMode::Stream
+        };
+        let report = execute(
+            &merged_actions,
+            &host_definition.config,
+            &context,
+            &mut backups,
+            &ExecutionOptions {
+                dry_run: options.dry,
+                output,
+                keep_going: options.keep_going,
+                dependencies: graph.package_dependencies(&host_definition.package),
+                jobs: options.jobs,
+                written: previous_lockfile
+                    .as_ref()
+                    .map(|lockfile| lockfile.written_checksums())
+                    .unwrap_or_default(),
+            },
+        );
+        if !options.dry {
+            report.print_summary();
+        }
+        let mut not_applied = report.not_applied();
+        if options.atomic && report.has_failures() {
+            not_applied.extend(rollback(
+                &report,
+                &previous_actions,
+                &host_definition.config,
+                &context,
+                &mut backups,
+                output,
+            ));
+        }
         if !options.dry || options.lock {
-            std::fs::write(
-                pathbuf![&std::env::current_dir()?, ".lockfile"],
-                &serialized_next_lockfile,
+            // Record only what has been applied, failures are retried next time
+            let applied = applied_actions(&next_system_actions, &previous_actions, &not_applied);
+            let mut lockfile = Lockfile::new(
+                &hostname,
+                attach_backups(&applied, &previous_actions, &backups.created),
             )?;
+            lockfile.preinstalled = preinstalled;
+            write_lockfile(&lockfile, root.as_deref())?;
+        }
+        if report.has_failures() {
+            bail!(
+                "{} action(s) failed",
+                report.count(|outcome| matches!(outcome, Outcome::Failed(_)))
+            );
         }
     }
     return Ok(());
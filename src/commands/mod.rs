@@ -1,6 +1,10 @@
 pub mod add;
+pub mod backups;
 pub mod generate;
 pub mod init;
 pub mod install;
 pub mod list;
 pub mod remove;
+pub mod status;
+pub mod sync;
+pub mod validate;
@@ -3,6 +3,7 @@ use pathbuf::pathbuf;
 use std::str::FromStr;
 
 use super::install::{run_install, RunInstallOptions};
+use crate::action::filter::ActionFilter;
 use crate::package::{DependencyDefinition, PackageDefinition};
 
 #[cfg(feature = "git")]
@@ -33,7 +34,11 @@ pub fn run_remove(hostname: String, options: RunRemoveOptions) -> Result<()> {
     definition.dependencies = definition
         .dependencies
         .into_iter()
-        .filter(|dependency| !old_dependencies.contains(dependency))
+        .filter(|dependency| {
+            !old_dependencies
+                .iter()
+                .any(|old| old.source == dependency.source && old.name == dependency.name)
+        })
         .collect();
     definition.save()?;
 
@@ -50,6 +55,14 @@ pub fn run_remove(hostname: String, options: RunRemoveOptions) -> Result<()> {
                 dry: false,
                 full: false,
                 lock: false,
+                keep_going: false,
+                atomic: false,
+                quiet: false,
+                jobs: 1,
+                repo: None,
+                root: None,
+                filter: ActionFilter::default(),
+                show_plan: false,
             },
         )?;
     }
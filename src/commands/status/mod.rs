@@ -0,0 +1,135 @@
+use anyhow::{bail, Result};
+use colored::Colorize;
+use pathbuf::pathbuf;
+use std::path::Path;
+
+use crate::{
+    action::{installed::InstalledPackages, FileOperation, PackageOperation, SystemAction},
+    helpers::checksum_file,
+    host::HostDefinition,
+    lockfile::{read_lockfile, Lockfile},
+};
+
+#[cfg(test)]
+mod test;
+
+pub struct RunStatusOptions {
+    pub root: Option<String>,
+}
+
+/// Compare the system with the lockfile, each difference as a tag and a message
+fn find_drifts<F>(
+    lockfile: &Lockfile,
+    pwd: &Path,
+    mut is_installed: F,
+) -> Result<Vec<(&'static str, String)>>
+where
+    F: FnMut(&String, &String) -> Result<Option<bool>>,
+{
+    let mut drifts = vec![];
+    for sysaction in lockfile.actions.iter() {
+        match sysaction {
+            SystemAction::File {
+                operation,
+                src,
+                dest,
+                origin,
+                checksum,
+                backup: _,
+                escalate: _,
+            } => {
+                let dest_path = pathbuf![dest];
+                if dest_path.symlink_metadata().is_err() {
+                    drifts.push(("MISSING", dest.clone()));
+                    continue;
+                }
+                match operation {
+                    FileOperation::Link => {
+                        let src_path = pathbuf![pwd, origin, src];
+                        match std::fs::read_link(&dest_path) {
+                            Err(_) => {
+                                drifts.push(("REPLACED", format!("{} is not a link", dest)));
+                            }
+                            Ok(target) if target != src_path => {
+                                drifts.push((
+                                    "MOVED",
+                                    format!(
+                                        "{} points to {} instead of {}",
+                                        dest,
+                                        target.to_string_lossy(),
+                                        src_path.to_string_lossy()
+                                    ),
+                                ));
+                            }
+                            Ok(_) if !dest_path.exists() => {
+                                drifts.push(("BROKEN", format!("{} target is missing", dest)));
+                            }
+                            Ok(_) => {}
+                        }
+                    }
+                    FileOperation::Copy | FileOperation::Template => {
+                        let expected = match operation {
+                            FileOperation::Template => checksum.as_ref(),
+                            _ => lockfile.hashes.get(dest),
+                        };
+                        if expected.is_some() && checksum_file(&dest_path).as_ref() != expected {
+                            drifts.push(("MODIFIED", dest.clone()));
+                        }
+                    }
+                    FileOperation::Remove => {}
+                }
+            }
+            // Without a query command, the package cannot be checked
+            SystemAction::Package {
+                operation: PackageOperation::Install,
+                source,
+                name,
+                origin: _,
+            } if is_installed(source, name)? == Some(false) => {
+                drifts.push(("UNINSTALLED", format!("{}:{}", source, name)));
+            }
+            _ => {}
+        }
+    }
+    return Ok(drifts);
+}
+
+/// Print every difference, failing when there is at least one
+fn report_drifts(drifts: &Vec<(&'static str, String)>) -> Result<()> {
+    for (tag, message) in drifts.iter() {
+        let tag = match *tag {
+            "MODIFIED" => tag.yellow(),
+            _ => tag.red(),
+        };
+        println!("[{}] {}", tag, message);
+    }
+    if !drifts.is_empty() {
+        bail!("{} difference(s) with the lockfile", drifts.len());
+    }
+    return Ok(());
+}
+
+pub fn run_status(hostname: String, options: RunStatusOptions) -> Result<()> {
+    let root = match &options.root {
+        Some(root) => Some(std::env::current_dir()?.join(root)),
+        None => None,
+    };
+    let mut host_definition = HostDefinition::from_path(&pathbuf!["hosts", &hostname])?;
+    host_definition.config.set_root(
+        &root
+            .as_ref()
+            .map_or("/".into(), |root| root.to_string_lossy()),
+    );
+    let lockfile = match read_lockfile(root.as_deref())? {
+        Some(lockfile) => lockfile,
+        None => bail!("No lockfile found, {} has never been installed", hostname),
+    };
+    let pwd = std::env::current_dir()?;
+    let mut installed = InstalledPackages::new(&host_definition.config);
+    let drifts = find_drifts(&lockfile, &pwd, |source, name| {
+        return installed.is_installed(source, name);
+    })?;
+    report_drifts(&drifts)?;
+    println!("{} matches the lockfile", hostname);
+    return Ok(());
+}
@@ -0,0 +1,112 @@
+use super::*;
+use crate::helpers::checksum;
+
+#[test]
+fn test_status_reports_drifts() {
+    let directory =
+        std::env::temp_dir().join(format!("dotstrap-test-status-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let package = directory.join("packages").join("shell");
+    let home = directory.join("home");
+    std::fs::create_dir_all(&package).unwrap();
+    std::fs::create_dir_all(&home).unwrap();
+    for name in ["bashrc", "zshrc", "profile", "inputrc", "gitconfig"] {
+        std::fs::write(package.join(name), name).unwrap();
+    }
+    let origin = package.to_string_lossy().to_string();
+    let file = |operation: FileOperation, name: &str| {
+        let checksum = match operation {
+            FileOperation::Template => Some(checksum(name.as_bytes())),
+            _ => None,
+        };
+        return SystemAction::File {
+            operation,
+            src: name.to_string(),
+            dest: home.join(name).to_string_lossy().to_string(),
+            origin: origin.clone(),
+            checksum,
+            backup: None,
+            escalate: false,
+        };
+    };
+    let package_action = |name: &str| SystemAction::Package {
+        operation: PackageOperation::Install,
+        source: "os".to_string(),
+        name: name.to_string(),
+        origin: origin.clone(),
+    };
+    let lockfile = Lockfile::new(
+        "test",
+        vec![
+            file(FileOperation::Link, "bashrc"),
+            file(FileOperation::Link, "zshrc"),
+            file(FileOperation::Link, "profile"),
+            file(FileOperation::Link, "inputrc"),
+            file(FileOperation::Copy, "gitconfig"),
+            file(FileOperation::Template, "vimrc"),
+            package_action("tmux"),
+            package_action("zsh"),
+        ],
+    )
+    .unwrap();
+    let installed = |_: &String, name: &String| Ok(Some(name == "zsh"));
+
+    // bashrc is left missing, zshrc is up to date
+    std::os::unix::fs::symlink(package.join("zshrc"), home.join("zshrc")).unwrap();
+    std::os::unix::fs::symlink(package.join("bashrc"), home.join("profile")).unwrap();
+    std::fs::write(home.join("inputrc"), "inputrc").unwrap();
+    std::fs::write(home.join("gitconfig"), "gitconfig").unwrap();
+    std::fs::write(home.join("vimrc"), "vimrc").unwrap();
+    assert_eq!(
+        vec![
+            ("MISSING", home.join("bashrc").to_string_lossy().to_string()),
+            (
+                "MOVED",
+                format!(
+                    "{} points to {} instead of {}",
+                    home.join("profile").to_string_lossy(),
+                    package.join("bashrc").to_string_lossy(),
+                    package.join("profile").to_string_lossy()
+                )
+            ),
+            (
+                "REPLACED",
+                format!("{} is not a link", home.join("inputrc").to_string_lossy())
+            ),
+            ("UNINSTALLED", "os:tmux".to_string()),
+        ],
+        find_drifts(&lockfile, &directory, installed).unwrap()
+    );
+
+    // The link target disappears and the copy and the template are edited
+    std::fs::remove_file(package.join("zshrc")).unwrap();
+    std::fs::write(home.join("gitconfig"), "edited").unwrap();
+    std::fs::write(home.join("vimrc"), "edited").unwrap();
+    let drifts = find_drifts(&lockfile, &directory, installed).unwrap();
+    assert_eq!(
+        vec![
+            (
+                "BROKEN",
+                format!("{} target is missing", home.join("zshrc").to_string_lossy())
+            ),
+            (
+                "MODIFIED",
+                home.join("gitconfig").to_string_lossy().to_string()
+            ),
+            ("MODIFIED", home.join("vimrc").to_string_lossy().to_string()),
+        ],
+        drifts
+            .iter()
+            .filter(|(tag, _)| ["BROKEN", "MODIFIED"].contains(tag))
+            .cloned()
+            .collect::<Vec<_>>()
+    );
+
+    // Any difference makes the command fail
+    assert!(report_drifts(&vec![]).is_ok());
+    assert_eq!(
+        "7 difference(s) with the lockfile",
+        report_drifts(&drifts).unwrap_err().to_string()
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
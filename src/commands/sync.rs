@@ -0,0 +1,55 @@
+use anyhow::Result;
+
+use super::install::{run_install, RunInstallOptions};
+
+#[cfg(feature = "git")]
+use crate::git;
+#[cfg(feature = "git")]
+use colored::Colorize;
+#[cfg(feature = "git")]
+use git2::Repository;
+
+pub struct RunSyncOptions {
+    /// How to install once pulled, the plan is always shown
+    pub install: RunInstallOptions,
+}
+
+/// Bring the repository up to date with its remote branch, showing what comes in
+#[cfg(feature = "git")]
+fn pull() -> Result<()> {
+    let repo = Repository::discover(std::env::current_dir()?)?;
+    git::fetch(&repo)?;
+    // Computed before the fast-forward, which refuses uncommitted changes
+    let incoming = git::incoming_commits(&repo)?;
+    git::fast_forward(&repo)?;
+    if incoming.is_empty() {
+        println!("Already up to date");
+        return Ok(());
+    }
+    println!("{} incoming commit(s):", incoming.len());
+    for commit in incoming.iter() {
+        let id = commit.id().to_string();
+        println!(
+            "  {} {}",
+            id[..7].yellow(),
+            commit.summary().unwrap_or_default()
+        );
+    }
+    return Ok(());
+}
+
+#[cfg(not(feature = "git"))]
+fn pull() -> Result<()> {
+    anyhow::bail!("sync needs dotstrap to be built with the git feature");
+}
+
+pub fn run_sync(hostname: String, options: RunSyncOptions) -> Result<()> {
+    pull()?;
+    return run_install(
+        hostname,
+        RunInstallOptions {
+            show_plan: true,
+            ..options.install
+        },
+    );
+}
@@ -0,0 +1,18 @@
+use anyhow::{bail, Result};
+use colored::Colorize;
+
+use crate::validation::validate_host;
+
+pub struct RunValidateOptions {}
+
+pub fn run_validate(hostname: String, _: RunValidateOptions) -> Result<()> {
+    let diagnostics = validate_host(&hostname)?;
+    for diagnostic in diagnostics.iter() {
+        println!("[{}] {}", "ERROR".red(), diagnostic);
+    }
+    if diagnostics.len() > 0 {
+        bail!("{} problem(s) found in {}", diagnostics.len(), hostname);
+    }
+    println!("{} is valid", hostname);
+    return Ok(());
+}
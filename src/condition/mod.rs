@@ -0,0 +1,201 @@
+use anyhow::{bail, Result};
+
+use crate::variables::Variables;
+
+#[cfg(test)]
+mod test;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Identifier(String),
+    Literal(String),
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    OpenParen,
+    CloseParen,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = expression.chars().collect();
+    let mut index = 0;
+    while index < chars.len() {
+        let current = chars[index];
+        let next = chars.get(index + 1).copied();
+        match current {
+            ' ' | '\t' | '\n' => {
+                index += 1;
+            }
+            '(' => {
+                tokens.push(Token::OpenParen);
+                index += 1;
+            }
+            ')' => {
+                tokens.push(Token::CloseParen);
+                index += 1;
+            }
+            '=' if next == Some('=') => {
+                tokens.push(Token::Equal);
+                index += 2;
+            }
+            '!' if next == Some('=') => {
+                tokens.push(Token::NotEqual);
+                index += 2;
+            }
+            '!' => {
+                tokens.push(Token::Not);
+                index += 1;
+            }
+            '&' if next == Some('&') => {
+                tokens.push(Token::And);
+                index += 2;
+            }
+            '|' if next == Some('|') => {
+                tokens.push(Token::Or);
+                index += 2;
+            }
+            '"' | '\'' => {
+                let quote = current;
+                let start = index + 1;
+                let mut end = start;
+                while end < chars.len() && chars[end] != quote {
+                    end += 1;
+                }
+                if end >= chars.len() {
+                    bail!("Unterminated string in \"{}\"", expression);
+                }
+                tokens.push(Token::Literal(chars[start..end].iter().collect()));
+                index = end + 1;
+            }
+            _ if current.is_alphanumeric() || current == '_' => {
+                let start = index;
+                while index < chars.len()
+                    && (chars[index].is_alphanumeric() || "_.-".contains(chars[index]))
+                {
+                    index += 1;
+                }
+                tokens.push(Token::Identifier(chars[start..index].iter().collect()));
+            }
+            _ => bail!("Unexpected character '{}' in \"{}\"", current, expression),
+        }
+    }
+    return Ok(tokens);
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    variables: &'a Variables,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.position);
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        return token;
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(env_name) = name.strip_prefix("env.") {
+            return std::env::var(env_name).ok();
+        }
+        return self.variables.get(name).cloned();
+    }
+
+    fn parse_or(&mut self) -> Result<bool> {
+        let mut value = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            let right = self.parse_and()?;
+            value = value || right;
+        }
+        return Ok(value);
+    }
+
+    fn parse_and(&mut self) -> Result<bool> {
+        let mut value = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            let right = self.parse_unary()?;
+            value = value && right;
+        }
+        return Ok(value);
+    }
+
+    fn parse_unary(&mut self) -> Result<bool> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(!self.parse_unary()?);
+        }
+        return self.parse_primary();
+    }
+
+    fn parse_operand(&mut self) -> Result<Option<String>> {
+        return match self.next() {
+            Some(Token::Identifier(name)) => Ok(self.lookup(&name)),
+            Some(Token::Literal(value)) => Ok(Some(value)),
+            token => bail!("Expected a variable or a string, found {:?}", token),
+        };
+    }
+
+    fn parse_primary(&mut self) -> Result<bool> {
+        if self.peek() == Some(&Token::OpenParen) {
+            self.next();
+            let value = self.parse_or()?;
+            if self.next() != Some(Token::CloseParen) {
+                bail!("Missing closing parenthesis");
+            }
+            return Ok(value);
+        }
+        let left = self.parse_operand()?;
+        return match self.peek() {
+            Some(Token::Equal) => {
+                self.next();
+                Ok(left == self.parse_operand()?)
+            }
+            Some(Token::NotEqual) => {
+                self.next();
+                Ok(left != self.parse_operand()?)
+            }
+            _ => Ok(is_truthy(&left)),
+        };
+    }
+}
+
+fn is_truthy(value: &Option<String>) -> bool {
+    return match value {
+        Some(value) => !["", "0", "false", "no"].contains(&value.to_lowercase().as_str()),
+        None => false,
+    };
+}
+
+/// Evaluate a `when:` expression such as `distro == "arch" && !env.CI`.
+/// Identifiers are looked up in the given variables, `env.NAME` reads
+/// the environment and quoted values are string literals.
+pub fn evaluate(expression: &str, variables: &Variables) -> Result<bool> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+        variables,
+    };
+    let value = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected {:?} in \"{}\"", token, expression);
+    }
+    return Ok(value);
+}
+
+/// Evaluate an optional condition, an absent condition always matches
+pub fn matches(when: &Option<String>, variables: &Variables) -> Result<bool> {
+    return match when {
+        Some(expression) => evaluate(expression, variables),
+        None => Ok(true),
+    };
+}
@@ -0,0 +1,19 @@
+use crate::{hashmap, variables::Variables};
+
+use super::*;
+
+#[test]
+fn test_condition_evaluation() {
+    let variables: Variables = hashmap![
+        "hostname".to_string() => "laptop".to_string(),
+        "distro".to_string() => "arch".to_string(),
+        "work".to_string() => "false".to_string()
+    ];
+    assert!(evaluate("hostname == \"laptop\"", &variables).unwrap());
+    assert!(evaluate("distro != 'debian' && !work", &variables).unwrap());
+    assert!(evaluate("work || (distro == \"arch\" && hostname)", &variables).unwrap());
+    assert!(!evaluate("missing", &variables).unwrap());
+    assert!(!evaluate("!(hostname == \"laptop\")", &variables).unwrap());
+    assert!(evaluate("hostname ==", &variables).is_err());
+    assert!(evaluate("(hostname", &variables).is_err());
+}
@@ -0,0 +1,263 @@
+use anyhow::{bail, Context, Result};
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    BranchType, Commit, ObjectType, Oid, Repository, StatusOptions,
+};
+use pathbuf::pathbuf;
+use std::path::{Path, PathBuf};
+
+use crate::helpers::checksum;
+
+#[cfg(test)]
+mod test;
+
+fn find_last_commit(repo: &Repository) -> Result<Commit<'_>, git2::Error> {
+    let obj = repo.head()?.resolve()?.peel(ObjectType::Commit)?;
+    return obj
+        .into_commit()
+        .map_err(|_| git2::Error::from_str("Couldn't find commit"));
+}
+
+pub fn add_and_commit(repo: &Repository, path: &PathBuf, message: &str) -> Result<Oid> {
+    let tree_id = {
+        let mut index = repo.index()?;
+        println!("Adding {} to the git index", path.to_string_lossy());
+        index.add_path(path)?;
+        index.write_tree()?
+    };
+    let signature = repo.signature()?;
+    let parent_commit = find_last_commit(repo)?;
+    let tree = repo.find_tree(tree_id)?;
+    let output = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &[&parent_commit],
+    )?;
+    // STRANGE:
+    // At this point, commit is created with correct changes on package.yml
+    // but it remains one staged version and one unstagged version
+    // they cancel themselves when added to index or removed from index
+    // The code below removes these artifacts, there is surely an underlying
+    // issue but for now, i don't know how to resolve properly it.
+    {
+        let mut index = repo.index()?;
+        index.add_path(path)?;
+        index.write()?
+    }
+
+    println!("Successfuly committed as \"{}\"", message);
+    return Ok(output);
+}
+
+/// Authenticate with the ssh agent or the git credential helpers
+fn remote_callbacks(config: &git2::Config) -> git2::RemoteCallbacks<'_> {
+    let mut remote_callbacks = git2::RemoteCallbacks::new();
+    remote_callbacks.credentials(|url, username, allowed| {
+        let mut cred_helper = git2::CredentialHelper::new(url);
+        cred_helper.config(config);
+        let creds = if allowed.contains(git2::CredentialType::SSH_KEY) {
+            let user = username
+                .map(|s| s.to_string())
+                .or_else(|| cred_helper.username.clone())
+                .unwrap_or("git".to_string());
+            git2::Cred::ssh_key_from_agent(&user)
+        } else if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+            git2::Cred::credential_helper(config, url, username)
+        } else if allowed.contains(git2::CredentialType::DEFAULT) {
+            git2::Cred::default()
+        } else {
+            Err(git2::Error::from_str("no authentication available"))
+        };
+        return creds;
+    });
+    return remote_callbacks;
+}
+
+pub fn push(repo: &Repository) -> Result<(), git2::Error> {
+    let config = repo.config()?;
+    let mut remote = repo
+        .find_remote("origin")
+        .expect("Missing git repo remote origin");
+
+    let head = repo.head().unwrap();
+    let branch_name = head.name().unwrap();
+
+    let mut push_options = git2::PushOptions::new();
+    push_options.remote_callbacks(remote_callbacks(&config));
+
+    remote
+        .push(
+            &[format!("{}:{}", &branch_name, &branch_name)],
+            Some(&mut push_options),
+        )
+        .expect("Cannot push");
+    println!("Successfuly pushed to {}", &branch_name[11..]);
+    return Ok(());
+}
+
+pub fn fetch(repo: &Repository) -> Result<()> {
+    let config = repo.config()?;
+    let mut remote = repo
+        .find_remote("origin")
+        .context("Missing git repo remote origin")?;
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options.remote_callbacks(remote_callbacks(&config));
+    remote
+        .fetch(&[] as &[&str], Some(&mut fetch_options), None)
+        .context(format!(
+            "Unable to fetch from {}",
+            remote.url().unwrap_or("origin")
+        ))?;
+    return Ok(());
+}
+
+/// Commit of the branch the current one follows
+fn upstream_commit(repo: &Repository) -> Result<Commit<'_>> {
+    let head = repo.head()?;
+    let branch_name = head.shorthand().unwrap_or("HEAD");
+    let branch = repo
+        .find_branch(branch_name, BranchType::Local)
+        .context(format!("{} is not a branch", branch_name))?;
+    let upstream = branch
+        .upstream()
+        .context(format!("{} does not follow a remote branch", branch_name))?;
+    return Ok(upstream.get().peel_to_commit()?);
+}
+
+/// Fails when tracked files have uncommitted changes
+pub fn check_clean(repo: &Repository) -> Result<()> {
+    let mut status_options = StatusOptions::new();
+    status_options
+        .include_untracked(false)
+        .include_ignored(false);
+    let statuses = repo.statuses(Some(&mut status_options))?;
+    if !statuses.is_empty() {
+        let paths: Vec<String> = statuses
+            .iter()
+            .filter_map(|entry| entry.path().map(|path| path.to_string()))
+            .collect();
+        bail!(
+            "Uncommitted changes in {}, commit or stash them first: {}",
+            repo.workdir().unwrap_or(repo.path()).to_string_lossy(),
+            paths.join(", ")
+        );
+    }
+    return Ok(());
+}
+
+/// Commits of the remote branch which the current one does not have yet, latest first
+pub fn incoming_commits(repo: &Repository) -> Result<Vec<Commit<'_>>> {
+    let upstream = upstream_commit(repo)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(upstream.id())?;
+    revwalk.hide(repo.head()?.peel_to_commit()?.id())?;
+    let mut commits = vec![];
+    for oid in revwalk {
+        commits.push(repo.find_commit(oid?)?);
+    }
+    return Ok(commits);
+}
+
+/// Move the current branch to the fetched one, only when it is behind it
+pub fn fast_forward(repo: &Repository) -> Result<()> {
+    check_clean(repo)?;
+    let upstream = upstream_commit(repo)?;
+    let annotated = repo.find_annotated_commit(upstream.id())?;
+    let (analysis, _) = repo.merge_analysis(&[&annotated])?;
+    if analysis.is_up_to_date() {
+        return Ok(());
+    }
+    if !analysis.is_fast_forward() {
+        bail!(
+            "{} has diverged from its remote branch, merge or rebase it first",
+            repo.head()?.shorthand().unwrap_or("HEAD")
+        );
+    }
+    // Update the files from the current commit before moving the branch
+    repo.checkout_tree(upstream.as_object(), Some(CheckoutBuilder::new().safe()))?;
+    repo.head()?
+        .set_target(upstream.id(), "dotstrap: fast-forward")?;
+    return Ok(());
+}
+
+/// URL to clone from: a path, a URL, or a shorthand like github.com/user/dotfiles
+fn repository_url(source: &str) -> Result<String> {
+    if Path::new(source).exists() {
+        return Ok(std::fs::canonicalize(source)?.to_string_lossy().to_string());
+    }
+    if source.contains("://") || source.contains('@') {
+        return Ok(source.to_string());
+    }
+    return Ok(format!("https://{}", source));
+}
+
+/// Directory name of a clone, the last part of its URL followed by a hash of the URL,
+/// as user/dotfiles and other-user/dotfiles are different repositories
+fn repository_name(url: &str) -> String {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".git");
+    let name = if name.is_empty() { "dotfiles" } else { name };
+    return format!("{}-{}", name, &checksum(url.as_bytes())[..8]);
+}
+
+fn repositories_dir() -> Result<PathBuf> {
+    if let Ok(data_home) = std::env::var("XDG_DATA_HOME") {
+        if !data_home.is_empty() {
+            return Ok(pathbuf![&data_home, "dotstrap", "repositories"]);
+        }
+    }
+    let home = std::env::var("HOME").context("Unable to find the HOME directory")?;
+    return Ok(pathbuf![
+        &home,
+        ".local",
+        "share",
+        "dotstrap",
+        "repositories"
+    ]);
+}
+
+/// Clone a dotstrap repository, or update the clone made before, and return where it is
+pub fn checkout_repository(source: &str) -> Result<PathBuf> {
+    let url = repository_url(source)?;
+    let path = pathbuf![&repositories_dir()?, &repository_name(&url)];
+    if !path.exists() {
+        println!("Cloning {} into {}", url, path.to_string_lossy());
+        let config = git2::Config::open_default()?;
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks(&config));
+        RepoBuilder::new()
+            .fetch_options(fetch_options)
+            .clone(&url, &path)
+            .context(format!("Unable to clone {}", url))?;
+        return Ok(path);
+    }
+    let repo = Repository::open(&path).context(format!(
+        "{} is not a git repository",
+        path.to_string_lossy()
+    ))?;
+    let origin = repo
+        .find_remote("origin")
+        .context("Missing git repo remote origin")?
+        .url()
+        .unwrap_or_default()
+        .to_string();
+    if origin != url {
+        bail!(
+            "{} already holds a clone of {}, not {}",
+            path.to_string_lossy(),
+            origin,
+            url
+        );
+    }
+    println!("Updating {}", path.to_string_lossy());
+    fetch(&repo)?;
+    fast_forward(&repo)?;
+    return Ok(path);
+}
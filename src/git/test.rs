@@ -0,0 +1,158 @@
+use super::*;
+
+/// Commit a file on the current branch of a repository, bare or not
+fn commit_file(repo: &Repository, name: &str, content: &str, message: &str) -> Oid {
+    let signature = git2::Signature::now("dotstrap", "dotstrap@example.com").unwrap();
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let mut tree = repo
+        .treebuilder(
+            parent
+                .as_ref()
+                .map(|parent| parent.tree().unwrap())
+                .as_ref(),
+        )
+        .unwrap();
+    tree.insert(name, repo.blob(content.as_bytes()).unwrap(), 0o100644)
+        .unwrap();
+    let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+    let parents: Vec<&Commit> = parent.iter().collect();
+    return repo
+        .commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap();
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("dotstrap-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    return path;
+}
+
+#[test]
+fn test_repository_urls() {
+    let directory = temp_dir("urls");
+    let bare_path = directory.join("dotfiles.git");
+    Repository::init_bare(&bare_path).unwrap();
+    let bare_url = std::fs::canonicalize(&bare_path)
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    assert_eq!(
+        bare_url,
+        repository_url(&bare_path.to_string_lossy()).unwrap()
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    for url in [
+        "file:///srv/git/dotfiles.git",
+        "ssh://git@example.com/user/dotfiles.git",
+        "git@github.com:user/dotfiles.git",
+        "https://github.com/user/dotfiles",
+    ] {
+        assert_eq!(url, repository_url(url).unwrap());
+        assert!(repository_name(url).starts_with("dotfiles-"));
+    }
+    assert_eq!(
+        "https://github.com/user/dotfiles",
+        repository_url("github.com/user/dotfiles").unwrap()
+    );
+    assert!(repository_name("/srv/git/").starts_with("git-"));
+    assert!(repository_name("file:///").starts_with("dotfiles-"));
+    // Two remotes of the same name are cloned side by side
+    assert_ne!(
+        repository_name("https://github.com/user/dotfiles"),
+        repository_name("https://github.com/other-user/dotfiles")
+    );
+    assert_eq!(
+        repository_name("https://github.com/user/dotfiles"),
+        repository_name("https://github.com/user/dotfiles")
+    );
+}
+
+#[test]
+fn test_checkout_local_repositories() {
+    let directory = temp_dir("checkout");
+    let bare_path = directory.join("dotfiles.git");
+    let bare = Repository::init_bare(&bare_path).unwrap();
+    commit_file(&bare, "README.md", "dotfiles", "First commit");
+    std::env::set_var("XDG_DATA_HOME", directory.join("data"));
+
+    let from_path = checkout_repository(&bare_path.to_string_lossy()).unwrap();
+    let file_url = format!(
+        "file://{}",
+        std::fs::canonicalize(&bare_path).unwrap().to_string_lossy()
+    );
+    let from_url = checkout_repository(&file_url).unwrap();
+    assert_ne!(from_path, from_url);
+    assert!(from_path.join("README.md").exists());
+    assert!(from_url.join("README.md").exists());
+
+    // The clone is updated on the next checkout
+    commit_file(&bare, "README.md", "updated", "Second commit");
+    assert_eq!(from_url, checkout_repository(&file_url).unwrap());
+    assert_eq!(
+        "updated",
+        std::fs::read_to_string(from_url.join("README.md")).unwrap()
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_pull_refuses_changes_and_divergence() {
+    let directory = temp_dir("pull");
+    let bare = Repository::init_bare(directory.join("dotfiles.git")).unwrap();
+    commit_file(&bare, "vimrc", "set number", "First commit");
+    let clone = Repository::clone(
+        &directory.join("dotfiles.git").to_string_lossy(),
+        directory.join("clone"),
+    )
+    .unwrap();
+    let vimrc = directory.join("clone").join("vimrc");
+
+    let second = commit_file(&bare, "vimrc", "set relativenumber", "Second commit");
+    fetch(&clone).unwrap();
+    let incoming: Vec<Oid> = incoming_commits(&clone)
+        .unwrap()
+        .iter()
+        .map(|commit| commit.id())
+        .collect();
+    assert_eq!(vec![second], incoming);
+    fast_forward(&clone).unwrap();
+    assert_eq!(
+        "set relativenumber",
+        std::fs::read_to_string(&vimrc).unwrap()
+    );
+    assert!(incoming_commits(&clone).unwrap().is_empty());
+
+    // Untracked files do not matter, modified ones do
+    std::fs::write(directory.join("clone").join("notes"), "todo").unwrap();
+    check_clean(&clone).unwrap();
+    std::fs::write(&vimrc, "set nonumber").unwrap();
+    let error = fast_forward(&clone).unwrap_err().to_string();
+    assert!(error.starts_with("Uncommitted changes in"), "{}", error);
+    clone
+        .checkout_head(Some(CheckoutBuilder::new().force()))
+        .unwrap();
+
+    commit_file(&bare, "vimrc", "set number", "Remote commit");
+    commit_file(&clone, "vimrc", "set list", "Local commit");
+    clone
+        .checkout_head(Some(CheckoutBuilder::new().force()))
+        .unwrap();
+    fetch(&clone).unwrap();
+    let error = fast_forward(&clone).unwrap_err().to_string();
+    assert!(
+        error.contains("has diverged from its remote branch"),
+        "{}",
+        error
+    );
+    assert_eq!("set list", std::fs::read_to_string(&vimrc).unwrap());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -1,3 +1,6 @@
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
 #[macro_export]
 macro_rules! hashmap {
     ($( $key: expr => $val: expr ),*) => {{
@@ -6,3 +9,15 @@ macro_rules! hashmap {
         map
     }}
 }
+
+pub fn checksum(content: &[u8]) -> String {
+    return format!("{:x}", Sha256::digest(content));
+}
+
+/// Checksum of a file content, None if it is not a readable file
+pub fn checksum_file(path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return None;
+    }
+    return Some(checksum(&std::fs::read(path).ok()?));
+}
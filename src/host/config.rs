@@ -1,32 +1,154 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::Path};
+use std::collections::HashMap;
+
+use super::preset::{find_preset, preset_names};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct PackageManagerCommands {
     pub install: String,
     pub uninstall: String,
     pub clean: Option<String>,
+    /// Succeeds when the package is installed
+    #[serde(alias = "is_installed")]
+    pub query: Option<String>,
+    /// Prints the installed packages, one per line
+    pub list_installed: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(try_from = "PackageManagerDefinition")]
 pub struct PackageManager {
     pub multiple: bool,
+    /// Attach the commands to the terminal, to answer their prompts
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interactive: bool,
+    /// Run the install, uninstall and clean commands with the escalation tool
+    #[serde(default, rename = "become", skip_serializing_if = "std::ops::Not::not")]
+    pub escalate: bool,
     pub commands: PackageManagerCommands,
 }
 
+#[derive(Deserialize, Default)]
+struct PackageManagerCommandsDefinition {
+    install: Option<String>,
+    uninstall: Option<String>,
+    clean: Option<String>,
+    #[serde(alias = "is_installed")]
+    query: Option<String>,
+    list_installed: Option<String>,
+}
+
+/// A package manager as written in config.yml, a preset overridden field by field
+#[derive(Deserialize)]
+struct PackageManagerDefinition {
+    preset: Option<String>,
+    multiple: Option<bool>,
+    #[serde(default)]
+    interactive: bool,
+    #[serde(rename = "become")]
+    escalate: Option<bool>,
+    #[serde(default)]
+    commands: PackageManagerCommandsDefinition,
+}
+
+impl TryFrom<PackageManagerDefinition> for PackageManager {
+    type Error = String;
+
+    fn try_from(definition: PackageManagerDefinition) -> Result<Self, Self::Error> {
+        let preset = match &definition.preset {
+            Some(name) => Some(find_preset(name).ok_or(format!(
+                "unknown preset {}, expected one of {}",
+                name,
+                preset_names().join(", ")
+            ))?),
+            None => None,
+        };
+        let commands = definition.commands;
+        let preset_commands = preset.as_ref().map(|preset| &preset.commands);
+        return Ok(PackageManager {
+            multiple: definition
+                .multiple
+                .or(preset.as_ref().map(|preset| preset.multiple))
+                .unwrap_or(false),
+            interactive: definition.interactive,
+            escalate: definition
+                .escalate
+                .or(preset.as_ref().map(|preset| preset.escalate))
+                .unwrap_or(false),
+            commands: PackageManagerCommands {
+                install: commands
+                    .install
+                    .or(preset_commands.map(|preset| preset.install.clone()))
+                    .ok_or("missing field `install`")?,
+                uninstall: commands
+                    .uninstall
+                    .or(preset_commands.map(|preset| preset.uninstall.clone()))
+                    .ok_or("missing field `uninstall`")?,
+                clean: commands
+                    .clean
+                    .or(preset_commands.and_then(|preset| preset.clean.clone())),
+                query: commands
+                    .query
+                    .or(preset_commands.and_then(|preset| preset.query.clone())),
+                list_installed: commands
+                    .list_installed
+                    .or(preset_commands.and_then(|preset| preset.list_installed.clone())),
+            },
+        });
+    }
+}
+
+/// Program running the commands which need root
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EscalationTool {
+    #[default]
+    Sudo,
+    Doas,
+    Run0,
+}
+
+impl EscalationTool {
+    pub fn program(&self) -> &'static str {
+        return match self {
+            EscalationTool::Sudo => "sudo",
+            EscalationTool::Doas => "doas",
+            EscalationTool::Run0 => "run0",
+        };
+    }
+
+    fn is_sudo(&self) -> bool {
+        return EscalationTool::Sudo.eq(self);
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct HostConfiguration {
+    /// Profiles merged under this host, from profiles/<name>
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extends: Vec<String>,
+    #[serde(default, skip_serializing_if = "EscalationTool::is_sudo")]
+    pub escalation: EscalationTool,
+    #[serde(default)]
     pub package_managers: HashMap<String, PackageManager>,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
 }
 
 impl HostConfiguration {
-    pub fn load(pathname: &Path) -> Result<HostConfiguration> {
-        let file_content = fs::read_to_string(pathname).expect(&format!(
-            "Unable to find file {}",
-            pathname.to_string_lossy()
-        ));
-        let host_configuration: HostConfiguration = serde_yaml::from_str(&file_content)?;
-        return Ok(host_configuration);
+    /// Replace the <root> placeholder of the package manager commands
+    pub fn set_root(&mut self, root: &str) {
+        for pm in self.package_managers.values_mut() {
+            let commands = &mut pm.commands;
+            for command in [&mut commands.install, &mut commands.uninstall]
+                .into_iter()
+                .chain(commands.clean.as_mut())
+                .chain(commands.query.as_mut())
+                .chain(commands.list_installed.as_mut())
+            {
+                *command = command.replace("<root>", root);
+            }
+        }
     }
 }
@@ -0,0 +1,35 @@
+use std::fs;
+
+use crate::variables::Variables;
+
+fn read_os_release(key: &str) -> Option<String> {
+    let content = fs::read_to_string("/etc/os-release").ok()?;
+    return content.lines().find_map(|line| {
+        let (name, value) = line.split_once('=')?;
+        if name.trim() != key {
+            return None;
+        }
+        return Some(value.trim().trim_matches('"').to_string());
+    });
+}
+
+/// Collect the facts about the current machine usable in `when:` conditions
+pub fn gather_facts(hostname: &str) -> Variables {
+    let mut facts = Variables::new();
+    facts.insert("hostname".to_string(), hostname.to_string());
+    facts.insert("os".to_string(), std::env::consts::OS.to_string());
+    facts.insert("arch".to_string(), std::env::consts::ARCH.to_string());
+    facts.insert(
+        "distro".to_string(),
+        read_os_release("ID").unwrap_or_default(),
+    );
+    facts.insert(
+        "distro_like".to_string(),
+        read_os_release("ID_LIKE").unwrap_or_default(),
+    );
+    facts.insert(
+        "user".to_string(),
+        std::env::var("USER").unwrap_or_default(),
+    );
+    return facts;
+}
@@ -2,22 +2,59 @@ use pathbuf::pathbuf;
 use std::path::PathBuf;
 
 use self::config::HostConfiguration;
+use self::profile::{load_host_layer, read_extends};
 use crate::package::PackageDefinition;
-use anyhow::Result;
+use crate::variables::Variables;
+use anyhow::{Context, Result};
 
 pub mod config;
+pub mod facts;
+pub mod preset;
+pub mod profile;
+
+#[cfg(test)]
+mod test;
 
 #[derive(Debug)]
 pub struct HostDefinition {
     pub package: PackageDefinition,
     pub config: HostConfiguration,
+    /// config.yml vars overridden by the optional vars.yml
+    pub vars: Variables,
 }
 
 impl HostDefinition {
+    /// Host merged over its profiles, deserialized once from the merged files
     pub fn from_path(pathbuf: &PathBuf) -> Result<HostDefinition> {
+        let layer = load_host_layer(pathbuf)?;
+        let has_profiles = !read_extends(&layer.config)?.is_empty();
+        let error_context = |file: &str| {
+            if has_profiles {
+                return format!(
+                    "Unable to merge {} with its profiles",
+                    pathbuf.to_string_lossy()
+                );
+            }
+            return format!(
+                "Unable to parse {}",
+                pathbuf![pathbuf, file].to_string_lossy()
+            );
+        };
+        // Going through text keeps the scalar coercion of the files
+        let config: HostConfiguration =
+            serde_yaml::from_str(&serde_yaml::to_string(&layer.config)?)
+                .context(error_context("config.yml"))?;
+        let vars: Option<Variables> = serde_yaml::from_str(&serde_yaml::to_string(&layer.vars)?)
+            .context(error_context("vars.yml"))?;
+        let package = PackageDefinition::parse(
+            &pathbuf![pathbuf, "package.yml"],
+            &serde_yaml::to_string(&layer.package)?,
+        )
+        .context(error_context("package.yml"))?;
         return Ok(HostDefinition {
-            package: PackageDefinition::load(&pathbuf![pathbuf, "package.yml"])?,
-            config: HostConfiguration::load(&pathbuf![pathbuf, "config.yml"])?,
+            package,
+            config,
+            vars: vars.unwrap_or_default(),
         });
     }
 }
@@ -43,17 +80,22 @@ links:
     copy: false
 ";
 
-pub const DEFAULT_HOST_CONFIG_CONTENT: &str = "package_managers:
+pub const DEFAULT_HOST_CONFIG_CONTENT: &str =
+    "# program running the commands which need root: sudo, doas or run0
+escalation: sudo
+package_managers:
   os:
-    # indicate that the package manager supports multiple packages at once
-    multiple: true
-    commands:
-      install: paru --needed --noconfirm -S <package>
-      uninstall: paru -Runs --noconfirm <package>
+    # built-in commands of a common package manager
+    # apt, dnf, pacman, paru, yay, zypper, apk, xbps, flatpak, snap, cargo, pipx, npm, go
+    preset: paru
   flatpak:
+    preset: flatpak
+    # indicate that the package manager supports multiple packages at once
     multiple: false
+    # run the commands with the escalation tool, presets needing root already do
+    become: true
+    # preset commands can be overridden one by one
     commands:
-      install: sudo flatpak install <package>
-      uninstall: sudo flatpak uninstall <package>
-      clean: sudo flatpak uninstall --unused
+      install: flatpak install --system <package>
+      uninstall: flatpak uninstall --system <package>
 ";
@@ -0,0 +1,179 @@
+use super::config::{PackageManager, PackageManagerCommands};
+
+struct Preset {
+    name: &'static str,
+    multiple: bool,
+    /// The commands need root
+    escalate: bool,
+    install: &'static str,
+    uninstall: &'static str,
+    clean: Option<&'static str>,
+    query: Option<&'static str>,
+    list_installed: Option<&'static str>,
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "apt",
+        multiple: true,
+        escalate: true,
+        install: "apt-get install -y <package>",
+        uninstall: "apt-get remove -y <package>",
+        clean: Some("apt-get autoremove -y"),
+        query: Some("dpkg -s <package>"),
+        list_installed: Some("dpkg-query -W -f=${Package}\\n"),
+    },
+    Preset {
+        name: "dnf",
+        multiple: true,
+        escalate: true,
+        install: "dnf install -y <package>",
+        uninstall: "dnf remove -y <package>",
+        clean: Some("dnf autoremove -y"),
+        query: Some("rpm -q <package>"),
+        list_installed: Some("rpm -qa --qf %{NAME}\\n"),
+    },
+    Preset {
+        name: "pacman",
+        multiple: true,
+        escalate: true,
+        install: "pacman -S --needed --noconfirm <package>",
+        uninstall: "pacman -Rns --noconfirm <package>",
+        clean: None,
+        query: Some("pacman -Q <package>"),
+        list_installed: Some("pacman -Qq"),
+    },
+    Preset {
+        name: "paru",
+        multiple: true,
+        escalate: false,
+        install: "paru -S --needed --noconfirm <package>",
+        uninstall: "paru -Runs --noconfirm <package>",
+        clean: Some("paru -c --noconfirm"),
+        query: Some("paru -Q <package>"),
+        list_installed: Some("paru -Qq"),
+    },
+    Preset {
+        name: "yay",
+        multiple: true,
+        escalate: false,
+        install: "yay -S --needed --noconfirm <package>",
+        uninstall: "yay -Rns --noconfirm <package>",
+        clean: Some("yay -Yc --noconfirm"),
+        query: Some("yay -Q <package>"),
+        list_installed: Some("yay -Qq"),
+    },
+    Preset {
+        name: "zypper",
+        multiple: true,
+        escalate: true,
+        install: "zypper --non-interactive install <package>",
+        uninstall: "zypper --non-interactive remove --clean-deps <package>",
+        clean: None,
+        query: Some("rpm -q <package>"),
+        list_installed: Some("rpm -qa --qf %{NAME}\\n"),
+    },
+    Preset {
+        name: "apk",
+        multiple: true,
+        escalate: true,
+        install: "apk add <package>",
+        uninstall: "apk del <package>",
+        clean: None,
+        query: Some("apk info -e <package>"),
+        list_installed: Some("apk info"),
+    },
+    Preset {
+        name: "xbps",
+        multiple: true,
+        escalate: true,
+        install: "xbps-install -y <package>",
+        uninstall: "xbps-remove -R -y <package>",
+        clean: Some("xbps-remove -o -y"),
+        query: Some("xbps-query <package>"),
+        list_installed: None,
+    },
+    Preset {
+        name: "flatpak",
+        multiple: true,
+        escalate: false,
+        install: "flatpak install -y --noninteractive <package>",
+        uninstall: "flatpak uninstall -y --noninteractive <package>",
+        clean: Some("flatpak uninstall -y --unused"),
+        query: Some("flatpak info <package>"),
+        list_installed: Some("flatpak list --columns=application"),
+    },
+    Preset {
+        name: "snap",
+        multiple: true,
+        escalate: true,
+        install: "snap install <package>",
+        uninstall: "snap remove <package>",
+        clean: None,
+        query: Some("snap list <package>"),
+        list_installed: Some("snap list"),
+    },
+    Preset {
+        name: "cargo",
+        multiple: true,
+        escalate: false,
+        install: "cargo install <package>",
+        uninstall: "cargo uninstall <package>",
+        clean: None,
+        query: None,
+        list_installed: Some("cargo install --list"),
+    },
+    Preset {
+        name: "pipx",
+        multiple: false,
+        escalate: false,
+        install: "pipx install <package>",
+        uninstall: "pipx uninstall <package>",
+        clean: None,
+        query: None,
+        list_installed: Some("pipx list --short"),
+    },
+    Preset {
+        name: "npm",
+        multiple: true,
+        escalate: false,
+        install: "npm install -g <package>",
+        uninstall: "npm uninstall -g <package>",
+        clean: None,
+        query: Some("npm ls -g <package>"),
+        list_installed: None,
+    },
+    Preset {
+        name: "go",
+        multiple: false,
+        escalate: false,
+        install: "go install <package>",
+        // go has no uninstall, the binary is left for the user to remove
+        uninstall:
+            "echo go cannot uninstall <package>, remove its binary from $(go env GOPATH)/bin",
+        clean: None,
+        query: None,
+        list_installed: None,
+    },
+];
+
+pub fn preset_names() -> Vec<&'static str> {
+    return PRESETS.iter().map(|preset| preset.name).collect();
+}
+
+/// Built-in definition of a common package manager
+pub fn find_preset(name: &str) -> Option<PackageManager> {
+    let preset = PRESETS.iter().find(|preset| preset.name == name)?;
+    return Some(PackageManager {
+        multiple: preset.multiple,
+        interactive: false,
+        escalate: preset.escalate,
+        commands: PackageManagerCommands {
+            install: preset.install.to_string(),
+            uninstall: preset.uninstall.to_string(),
+            clean: preset.clean.map(|command| command.to_string()),
+            query: preset.query.map(|command| command.to_string()),
+            list_installed: preset.list_installed.map(|command| command.to_string()),
+        },
+    });
+}
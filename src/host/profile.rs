@@ -0,0 +1,189 @@
+use anyhow::{bail, Context, Result};
+use pathbuf::pathbuf;
+use serde_yaml::Value;
+use std::path::Path;
+
+pub const PROFILES_DIRECTORY: &str = "profiles";
+
+/// config.yml, package.yml and vars.yml of a host or a profile
+#[derive(Debug, Default)]
+pub struct HostLayer {
+    pub config: Value,
+    pub package: Value,
+    pub vars: Value,
+}
+
+impl HostLayer {
+    /// Missing files are empty, vars.yml overrides the vars of config.yml
+    fn read(path: &Path) -> Result<HostLayer> {
+        let config = read_value(&pathbuf![path, "config.yml"])?;
+        let vars = merge_values(
+            config.get("vars").cloned().unwrap_or_default(),
+            read_value(&pathbuf![path, "vars.yml"])?,
+        );
+        return Ok(HostLayer {
+            config,
+            package: read_value(&pathbuf![path, "package.yml"])?,
+            vars,
+        });
+    }
+
+    fn merge(self, overlay: HostLayer) -> HostLayer {
+        return HostLayer {
+            config: merge_values(self.config, overlay.config),
+            package: merge_values(self.package, overlay.package),
+            vars: merge_values(self.vars, overlay.vars),
+        };
+    }
+}
+
+/// Maps are merged, lists appended and scalars overridden
+pub fn merge_values(base: Value, overlay: Value) -> Value {
+    return match (base, overlay) {
+        (Value::Mapping(mut base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay.into_iter() {
+                match base.get_mut(&key) {
+                    Some(base_value) => {
+                        let merged = merge_values(std::mem::take(base_value), value);
+                        *base_value = merged;
+                    }
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+            Value::Mapping(base)
+        }
+        (Value::Sequence(mut base), Value::Sequence(overlay)) => {
+            base.extend(overlay);
+            Value::Sequence(base)
+        }
+        // An empty key does not erase what the profiles declared
+        (base, Value::Null) => base,
+        (_, overlay) => overlay,
+    };
+}
+
+fn read_value(path: &Path) -> Result<Value> {
+    if !path.exists() {
+        return Ok(Value::Null);
+    }
+    let file_content = std::fs::read_to_string(path)?;
+    return serde_yaml::from_str(&file_content)
+        .context(format!("Unable to parse {}", path.to_string_lossy()));
+}
+
+pub fn read_extends(config: &Value) -> Result<Vec<String>> {
+    return match config.get("extends") {
+        Some(extends) => Ok(serde_yaml::from_value(extends.clone())
+            .context("extends must be a list of profile names")?),
+        None => Ok(vec![]),
+    };
+}
+
+/// Make the link sources of a profile relative to the host directory
+fn rebase_links(package: &mut Value, profile_path: &Path, host_path: &Path) {
+    let prefix = pathbuf![&"../".repeat(host_path.components().count()), profile_path];
+    let rebase = |src: &str| -> String {
+        if src.starts_with('/') || src.starts_with('~') || src.starts_with('$') {
+            return src.to_string();
+        }
+        return pathbuf![&prefix, src].to_string_lossy().to_string();
+    };
+    let links = match package.get_mut("links") {
+        Some(Value::Sequence(links)) => links,
+        _ => return,
+    };
+    for link in links.iter_mut() {
+        match link {
+            Value::String(link_string) => {
+                if let Some((src, dest)) = link_string.split_once(':') {
+                    *link_string = format!("{}:{}", rebase(src), dest);
+                }
+            }
+            Value::Mapping(link_map) => {
+                if let Some(Value::String(src)) = link_map.get_mut("src") {
+                    *src = rebase(src);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn load_profile(
+    name: &String,
+    host_path: &Path,
+    stack: &mut Vec<String>,
+    loaded: &mut Vec<String>,
+) -> Result<HostLayer> {
+    if stack.contains(name) {
+        stack.push(name.clone());
+        bail!("Profile cycle detected: {}", stack.join(" -> "));
+    }
+    if loaded.contains(name) {
+        return Ok(HostLayer::default());
+    }
+    let profile_path = pathbuf![PROFILES_DIRECTORY, name];
+    if !profile_path.is_dir() {
+        bail!(
+            "Profile {} not found at {}",
+            name,
+            profile_path.to_string_lossy()
+        );
+    }
+    let mut profile = HostLayer::read(&profile_path)?;
+    rebase_links(&mut profile.package, &profile_path, host_path);
+    stack.push(name.clone());
+    let mut layer = HostLayer::default();
+    for parent in read_extends(&profile.config)?.iter() {
+        layer = layer.merge(load_profile(parent, host_path, stack, loaded)?);
+    }
+    stack.pop();
+    loaded.push(name.clone());
+    return Ok(layer.merge(profile));
+}
+
+/// Merge the profiles in order, each one over the profiles it extends.
+/// A profile reached twice is merged once.
+pub fn load_profiles(extends: &Vec<String>, host_path: &Path) -> Result<HostLayer> {
+    let mut loaded = vec![];
+    let mut layer = HostLayer::default();
+    for name in extends.iter() {
+        layer = layer.merge(load_profile(name, host_path, &mut vec![], &mut loaded)?);
+    }
+    return Ok(layer);
+}
+
+/// Names of the profiles in the order they are merged, each one once
+pub fn profile_names(extends: &Vec<String>) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for name in extends.iter() {
+        let config = read_value(&pathbuf![PROFILES_DIRECTORY, name, "config.yml"])?;
+        for parent in profile_names(&read_extends(&config)?)? {
+            if !names.contains(&parent) {
+                names.push(parent);
+            }
+        }
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    return Ok(names);
+}
+
+/// Files of a host directory merged over the profiles it extends
+pub fn load_host_layer(host_path: &Path) -> Result<HostLayer> {
+    let config_path = pathbuf![host_path, "config.yml"];
+    if !config_path.exists() {
+        bail!("Unable to find file {}", config_path.to_string_lossy());
+    }
+    let host = HostLayer::read(host_path)?;
+    let extends = read_extends(&host.config)?;
+    let mut layer = load_profiles(&extends, host_path)?.merge(host);
+    // The merged list would repeat the profiles of the profiles
+    if let Value::Mapping(config) = &mut layer.config {
+        config.insert("extends".into(), serde_yaml::to_value(extends)?);
+    }
+    return Ok(layer);
+}
@@ -0,0 +1,151 @@
+use super::config::HostConfiguration;
+use super::*;
+use std::path::Path;
+
+#[test]
+fn test_package_manager_preset_overrides() {
+    let config: HostConfiguration = serde_yaml::from_str(DEFAULT_HOST_CONFIG_CONTENT).unwrap();
+    let os = &config.package_managers["os"];
+    assert!(os.multiple);
+    assert_eq!(
+        "paru -S --needed --noconfirm <package>",
+        os.commands.install
+    );
+    assert_eq!(Some("paru -Qq".to_string()), os.commands.list_installed);
+    assert!(!os.escalate);
+
+    let flatpak = &config.package_managers["flatpak"];
+    assert!(!flatpak.multiple);
+    assert!(flatpak.escalate);
+    assert_eq!(
+        "flatpak install --system <package>",
+        flatpak.commands.install
+    );
+    assert_eq!(
+        Some("flatpak info <package>".to_string()),
+        flatpak.commands.query
+    );
+
+    let unknown =
+        serde_yaml::from_str::<HostConfiguration>("package_managers:\n  os:\n    preset: brew\n");
+    assert!(unknown.is_err());
+    let incomplete = serde_yaml::from_str::<HostConfiguration>(
+        "package_managers:\n  os:\n    commands:\n      install: apt install <package>\n",
+    );
+    assert!(incomplete.is_err());
+
+    // Escalation comes from become, with the tool of the host
+    for name in preset::preset_names() {
+        let preset = preset::find_preset(name).unwrap();
+        for command in [&preset.commands.install, &preset.commands.uninstall] {
+            assert!(!command.contains("sudo"), "{}", command);
+        }
+    }
+    assert_eq!(
+        "paru -Runs --noconfirm <package>",
+        preset::find_preset("paru").unwrap().commands.uninstall
+    );
+}
+
+#[test]
+fn test_profile_merge() {
+    let base: serde_yaml::Value = serde_yaml::from_str(
+        "dependencies: [git, dot:zsh]\nvars: { editor: vim, theme: dark }\npost_install: echo base\nlinks: [vimrc:~/.vimrc]",
+    )
+    .unwrap();
+    let overlay: serde_yaml::Value = serde_yaml::from_str(
+        "dependencies: [firefox]\nvars: { editor: nvim }\npost_install: echo host\nlinks:",
+    )
+    .unwrap();
+    let expected: serde_yaml::Value = serde_yaml::from_str(
+        "dependencies: [git, dot:zsh, firefox]\nvars: { editor: nvim, theme: dark }\npost_install: echo host\nlinks: [vimrc:~/.vimrc]",
+    )
+    .unwrap();
+    assert_eq!(expected, profile::merge_values(base, overlay));
+}
+
+#[test]
+fn test_profile_loading() {
+    let directory =
+        std::env::temp_dir().join(format!("dotstrap-test-profiles-{}", std::process::id()));
+    let write = |path: &str, content: &str| {
+        let path = directory.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        "profiles/base/config.yml",
+        "vars: { editor: vim, theme: dark }\npackage_managers:\n  os:\n    preset: apt",
+    );
+    write(
+        "profiles/base/package.yml",
+        "links:\n  - vimrc:~/.vimrc\n  - src: /etc/inputrc\n    dest: ~/.inputrc",
+    );
+    write(
+        "profiles/work/config.yml",
+        "extends: [base]\nvars: { editor: nvim }",
+    );
+    write(
+        "profiles/work/package.yml",
+        "dependencies: [git]\nlinks:\n  - src: gitconfig\n    dest: ~/.gitconfig",
+    );
+    // A host overrides a single field of the package manager of its profiles
+    write(
+        "hosts/laptop/config.yml",
+        "extends: [work]\npackage_managers:\n  os:\n    multiple: false\n    commands:\n      install: apt-get install -y <package>",
+    );
+    write("hosts/laptop/package.yml", "dependencies: [tmux]");
+    write(
+        "hosts/desktop/config.yml",
+        "package_managers:\n  os:\n    preset: apt\n    multiple: false",
+    );
+    write("hosts/desktop/package.yml", "dependencies: [tmux]");
+    write("profiles/loop-a/config.yml", "extends: [loop-b]");
+    write("profiles/loop-b/config.yml", "extends: [loop-a]");
+    // Profiles are found from the repository root
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(&directory).unwrap();
+    let layer = profile::load_profiles(&vec!["work".to_string()], Path::new("hosts/laptop"));
+    let cycle = profile::load_profiles(&vec!["loop-a".to_string()], Path::new("hosts/laptop"));
+    let laptop = HostDefinition::from_path(&PathBuf::from("hosts/laptop"));
+    let desktop = HostDefinition::from_path(&PathBuf::from("hosts/desktop"));
+    let laptop_diagnostics = crate::validation::validate_host("laptop");
+    std::env::set_current_dir(current_dir).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let layer = layer.unwrap();
+    let expected: serde_yaml::Value = serde_yaml::from_str(
+        "links:\n  - ../../profiles/base/vimrc:~/.vimrc\n  - src: /etc/inputrc\n    dest: ~/.inputrc\n  - src: ../../profiles/work/gitconfig\n    dest: ~/.gitconfig\ndependencies: [git]",
+    )
+    .unwrap();
+    assert_eq!(expected, layer.package);
+    let expected_vars: serde_yaml::Value =
+        serde_yaml::from_str("{ editor: nvim, theme: dark }").unwrap();
+    assert_eq!(expected_vars, layer.vars);
+    assert_eq!(
+        "Profile cycle detected: loop-a -> loop-b -> loop-a",
+        cycle.unwrap_err().to_string()
+    );
+
+    let laptop = laptop.unwrap();
+    let os = &laptop.config.package_managers["os"];
+    assert!(!os.multiple);
+    assert_eq!("apt-get install -y <package>", os.commands.install);
+    assert_eq!(
+        preset::find_preset("apt").unwrap().commands.uninstall,
+        os.commands.uninstall
+    );
+    assert_eq!(vec!["work".to_string()], laptop.config.extends);
+    assert_eq!("nvim", laptop.vars["editor"]);
+    let desktop = desktop.unwrap();
+    assert!(!desktop.config.package_managers["os"].multiple);
+    assert!(desktop.config.extends.is_empty());
+    // Only the missing link sources of the profiles are reported
+    for diagnostic in laptop_diagnostics.unwrap().iter() {
+        assert!(
+            diagnostic.message.starts_with("Link source"),
+            "{}",
+            diagnostic
+        );
+    }
+}
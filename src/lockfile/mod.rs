@@ -0,0 +1,934 @@
+use anyhow::{bail, Context, Result};
+use pathbuf::pathbuf;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::action::FileOperation;
+use crate::action::PackageOperation;
+use crate::action::ScriptOperation;
+use crate::action::SystemAction;
+use crate::helpers::{checksum, checksum_file};
+use crate::package::RunPolicy;
+
+#[cfg(test)]
+mod test;
+
+pub const LOCKFILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Lockfile {
+    pub version: u32,
+    pub hostname: String,
+    pub dotstrap_version: String,
+    /// Unix timestamp of the installation
+    pub timestamp: u64,
+    /// Commit of the dotstrap repository used for the installation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Content hashes of the linked/copied sources by dest and of the scripts by origin
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hashes: BTreeMap<String, String>,
+    /// Packages found on the system before dotstrap wanted them, as source:name
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub preinstalled: BTreeSet<String>,
+    pub actions: Vec<SystemAction>,
+}
+
+impl Lockfile {
+    pub fn new(hostname: &str, actions: Vec<SystemAction>) -> Result<Lockfile> {
+        return Ok(Lockfile {
+            version: LOCKFILE_VERSION,
+            hostname: hostname.to_string(),
+            dotstrap_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            commit: current_commit(),
+            hashes: compute_hashes(&actions),
+            preinstalled: BTreeSet::new(),
+            actions,
+        });
+    }
+
+    /// Checksums of the copies and templates written by the installation, by destination
+    pub fn written_checksums(&self) -> BTreeMap<String, String> {
+        let mut written = BTreeMap::new();
+        for sysaction in self.actions.iter() {
+            let checksum = match sysaction {
+                SystemAction::File {
+                    operation: FileOperation::Copy,
+                    dest,
+                    ..
+                } => self.hashes.get(dest),
+                SystemAction::File {
+                    operation: FileOperation::Template,
+                    checksum,
+                    ..
+                } => checksum.as_ref(),
+                _ => None,
+            };
+            if let (SystemAction::File { dest, .. }, Some(checksum)) = (sysaction, checksum) {
+                written.insert(dest.clone(), checksum.clone());
+            }
+        }
+        return written;
+    }
+
+    /// Upgrade a lockfile from any known format to the current one
+    pub fn parse(content: &str) -> Result<Lockfile> {
+        let value: serde_yaml::Value = serde_yaml::from_str(content)?;
+        // Version 0: a bare list of actions without any header
+        if value.is_sequence() {
+            let actions: Vec<SystemAction> = serde_yaml::from_value(value)?;
+            return Ok(Lockfile {
+                version: LOCKFILE_VERSION,
+                hostname: String::new(),
+                dotstrap_version: String::new(),
+                timestamp: 0,
+                commit: None,
+                hashes: BTreeMap::new(),
+                preinstalled: BTreeSet::new(),
+                actions,
+            });
+        }
+        let version = value.get("version").and_then(|version| version.as_u64());
+        match version {
+            Some(version) if version <= LOCKFILE_VERSION as u64 => {}
+            Some(version) => bail!(
+                "Lockfile version {} is not supported, upgrade dotstrap",
+                version
+            ),
+            None => bail!("Lockfile has no version"),
+        }
+        return Ok(serde_yaml::from_value(value)?);
+    }
+}
+
+#[cfg(feature = "git")]
+fn current_commit() -> Option<String> {
+    let repo = git2::Repository::discover(std::env::current_dir().ok()?).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    return Some(commit.id().to_string());
+}
+
+#[cfg(not(feature = "git"))]
+fn current_commit() -> Option<String> {
+    return None;
+}
+
+fn compute_hashes(system_actions: &Vec<SystemAction>) -> BTreeMap<String, String> {
+    let mut hashes = BTreeMap::new();
+    for sysaction in system_actions.iter() {
+        match sysaction {
+            SystemAction::File {
+                operation: FileOperation::Link | FileOperation::Copy,
+                src,
+                dest,
+                origin,
+                ..
+            } => {
+                if let Some(hash) = checksum_file(&pathbuf![origin, src]) {
+                    hashes.insert(dest.clone(), hash);
+                }
+            }
+            SystemAction::Script {
+                operation: ScriptOperation::Run,
+                script,
+                origin,
+                ..
+            } => {
+                hashes.insert(origin.clone(), checksum(script.as_bytes()));
+            }
+            _ => {}
+        }
+    }
+    return hashes;
+}
+
+/// The lockfile of an installation into an alternate root is kept in it,
+/// to leave alone the one of the repository
+fn lockfile_path(root: Option<&Path>) -> Result<PathBuf> {
+    return Ok(match root {
+        Some(root) => pathbuf![root, ".dotstrap.lockfile"],
+        None => pathbuf![&std::env::current_dir()?, ".lockfile"],
+    });
+}
+
+pub fn read_lockfile(root: Option<&Path>) -> Result<Option<Lockfile>> {
+    let lockfile_path = lockfile_path(root)?;
+    if !lockfile_path.exists() {
+        return Ok(None);
+    }
+    let file_content = std::fs::read_to_string(&lockfile_path)?;
+    let lockfile = Lockfile::parse(&file_content).context(format!(
+        "Unable to parse {}",
+        lockfile_path.to_string_lossy()
+    ))?;
+    return Ok(Some(lockfile));
+}
+
+pub fn write_lockfile(lockfile: &Lockfile, root: Option<&Path>) -> Result<()> {
+    let lockfile_path = lockfile_path(root)?;
+    if let Some(directory) = lockfile_path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    std::fs::write(lockfile_path, serde_yaml::to_string(lockfile)?)?;
+    return Ok(());
+}
+
+/// Compare two actions, ignoring what is only recorded once applied
+/// and honouring the run policy of the scripts.
+/// System packages are identified by source and name, their origin is only provenance
+fn is_same_action(left: &SystemAction, right: &SystemAction) -> bool {
+    return match (left, right) {
+        (
+            SystemAction::Package {
+                operation: left_operation,
+                source: left_source,
+                name: left_name,
+                origin: _,
+            },
+            SystemAction::Package {
+                operation: right_operation,
+                source: right_source,
+                name: right_name,
+                origin: _,
+            },
+        ) => {
+            left_operation == right_operation
+                && left_source == right_source
+                && left_name == right_name
+        }
+        (
+            SystemAction::Script {
+                operation: left_operation,
+                script: left_script,
+                origin: left_origin,
+                policy: left_policy,
+                checksum: left_checksum,
+                interactive: _,
+                escalate: _,
+            },
+            SystemAction::Script {
+                operation: right_operation,
+                script: right_script,
+                origin: right_origin,
+                policy: right_policy,
+                checksum: right_checksum,
+                interactive: _,
+                escalate: _,
+            },
+        ) => {
+            if left_operation != right_operation
+                || left_origin != right_origin
+                || left_policy != right_policy
+            {
+                return false;
+            }
+            match left_policy {
+                RunPolicy::Once => true,
+                RunPolicy::OnChange => {
+                    left_script == right_script && left_checksum == right_checksum
+                }
+                RunPolicy::Always => false,
+            }
+        }
+        (
+            SystemAction::File {
+                operation: left_operation,
+                src: left_src,
+                dest: left_dest,
+                origin: left_origin,
+                checksum: left_checksum,
+                backup: _,
+                escalate: _,
+            },
+            SystemAction::File {
+                operation: right_operation,
+                src: right_src,
+                dest: right_dest,
+                origin: right_origin,
+                checksum: right_checksum,
+                backup: _,
+                escalate: _,
+            },
+        ) => {
+            left_operation == right_operation
+                && left_src == right_src
+                && left_dest == right_dest
+                && left_origin == right_origin
+                && left_checksum == right_checksum
+        }
+        _ => left == right,
+    };
+}
+
+fn make_difference(from: &Vec<SystemAction>, to: &Vec<SystemAction>) -> Vec<SystemAction> {
+    let mut difference = vec![];
+    for item in from.into_iter() {
+        if !to.iter().any(|other| is_same_action(item, other)) {
+            difference.push(item.clone());
+        }
+    }
+    return difference;
+}
+
+/// Keep a single operation per system package wanted by several dot packages
+fn unique_packages(system_actions: Vec<SystemAction>) -> Vec<SystemAction> {
+    let mut unique_actions: Vec<SystemAction> = vec![];
+    for sysaction in system_actions.into_iter() {
+        let is_duplicate = matches!(sysaction, SystemAction::Package { .. })
+            && unique_actions
+                .iter()
+                .any(|other| is_same_action(&sysaction, other));
+        if !is_duplicate {
+            unique_actions.push(sysaction);
+        }
+    }
+    return unique_actions;
+}
+
+fn file_destinations(system_actions: &Vec<SystemAction>) -> Vec<&String> {
+    return system_actions
+        .iter()
+        .filter_map(|sysaction| match sysaction {
+            SystemAction::File { dest, .. } => Some(dest),
+            _ => None,
+        })
+        .collect();
+}
+
+fn inverse_additive_actions(system_actions: &Vec<SystemAction>) -> Vec<SystemAction> {
+    return system_actions
+        .into_iter()
+        .filter(|sysaction| match sysaction {
+            SystemAction::Package {
+                operation,
+                source: _,
+                name: _,
+                origin: _,
+            } => PackageOperation::Uninstall.ne(operation),
+            SystemAction::File {
+                operation,
+                src: _,
+                dest: _,
+                origin: _,
+                checksum: _,
+                backup: _,
+                escalate: _,
+            } => FileOperation::Remove.ne(operation),
+            _ => false,
+        })
+        .map(|sysaction| match sysaction {
+            SystemAction::Package {
+                operation: _,
+                source,
+                name,
+                origin,
+            } => {
+                return SystemAction::Package {
+                    operation: PackageOperation::Uninstall,
+                    source: source.clone(),
+                    name: name.clone(),
+                    origin: origin.clone(),
+                };
+            }
+            SystemAction::File {
+                operation: _,
+                src: _,
+                dest,
+                origin,
+                checksum: _,
+                backup,
+                escalate,
+            } => {
+                return SystemAction::File {
+                    operation: FileOperation::Remove,
+                    src: String::new(),
+                    dest: dest.clone(),
+                    origin: origin.clone(),
+                    checksum: None,
+                    backup: backup.clone(),
+                    escalate: *escalate,
+                };
+            }
+            _ => panic!["Cannot reverse other actions than File & Package !"],
+        })
+        .rev()
+        .collect();
+}
+
+/// Record the backups on the file actions of the next lockfile,
+/// the ones made during this run or kept from the previous lockfile
+pub fn attach_backups(
+    next_system_actions: &Vec<SystemAction>,
+    previous_actions: &Vec<SystemAction>,
+    created_backups: &BTreeMap<String, String>,
+) -> Vec<SystemAction> {
+    let mut backups = BTreeMap::<&String, &String>::new();
+    for sysaction in previous_actions.iter() {
+        if let SystemAction::File {
+            dest,
+            backup: Some(backup),
+            ..
+        } = sysaction
+        {
+            backups.insert(dest, backup);
+        }
+    }
+    // The first backup of a destination holds the file dotstrap found there
+    for (dest, backup) in created_backups.iter() {
+        backups.entry(dest).or_insert(backup);
+    }
+    return next_system_actions
+        .iter()
+        .map(|sysaction| match sysaction {
+            SystemAction::File {
+                operation,
+                src,
+                dest,
+                origin,
+                checksum,
+                backup: _,
+                escalate,
+            } => SystemAction::File {
+                operation: operation.clone(),
+                src: src.clone(),
+                dest: dest.clone(),
+                origin: origin.clone(),
+                checksum: checksum.clone(),
+                backup: backups.get(dest).map(|backup| backup.to_string()),
+                escalate: *escalate,
+            },
+            _ => sysaction.clone(),
+        })
+        .collect();
+}
+
+fn package_path(sysaction: &SystemAction) -> &str {
+    let origin = match sysaction {
+        SystemAction::Package { origin, .. } => origin,
+        SystemAction::Script { origin, .. } => origin,
+        SystemAction::File { origin, .. } => origin,
+    };
+    return origin.split(':').next().unwrap();
+}
+
+fn package_paths(system_actions: &Vec<SystemAction>) -> Vec<&str> {
+    let mut paths: Vec<&str> = vec![];
+    for sysaction in system_actions.iter() {
+        let path = package_path(sysaction);
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    return paths;
+}
+
+fn uninstall_script(
+    previous_actions: &Vec<SystemAction>,
+    package: &str,
+    hook: &str,
+) -> Option<SystemAction> {
+    let origin = format!("{}:{}", package, hook);
+    return previous_actions
+        .iter()
+        .find_map(|sysaction| match sysaction {
+            SystemAction::Script {
+                operation: ScriptOperation::OnUninstall,
+                script,
+                origin: script_origin,
+                policy: _,
+                checksum: _,
+                interactive,
+                escalate,
+            } if origin.eq(script_origin) => Some(SystemAction::Script {
+                operation: ScriptOperation::Run,
+                script: script.clone(),
+                origin: origin.clone(),
+                policy: RunPolicy::Always,
+                checksum: None,
+                interactive: *interactive,
+                escalate: *escalate,
+            }),
+            _ => None,
+        });
+}
+
+/// Order the removals so each package that disappears from the plan
+/// is surrounded by its pre_uninstall and post_uninstall scripts
+fn wrap_with_uninstall_scripts(
+    delete_actions: Vec<SystemAction>,
+    previous_actions: &Vec<SystemAction>,
+    next_system_actions: &Vec<SystemAction>,
+) -> Vec<SystemAction> {
+    let next_packages = package_paths(next_system_actions);
+    let removed_packages: Vec<&str> = package_paths(previous_actions)
+        .into_iter()
+        .filter(|path| !next_packages.contains(path))
+        .rev()
+        .collect();
+    let mut ordered_actions: Vec<SystemAction> = delete_actions
+        .iter()
+        .filter(|sysaction| !removed_packages.contains(&package_path(sysaction)))
+        .cloned()
+        .collect();
+    for package in removed_packages.into_iter() {
+        ordered_actions.extend(uninstall_script(previous_actions, package, "pre_uninstall"));
+        ordered_actions.extend(
+            delete_actions
+                .iter()
+                .filter(|sysaction| package_path(sysaction) == package)
+                .cloned(),
+        );
+        ordered_actions.extend(uninstall_script(
+            previous_actions,
+            package,
+            "post_uninstall",
+        ));
+    }
+    return ordered_actions;
+}
+
+fn is_runnable(sysaction: &SystemAction) -> bool {
+    return !matches!(
+        sysaction,
+        SystemAction::Script {
+            operation: ScriptOperation::OnUninstall,
+            ..
+        }
+    );
+}
+
+/// Actions wanted after an installation limited to some kinds of actions:
+/// the accepted next ones, the others staying as the previous installation left them
+pub fn filter_planned_actions<F>(
+    next_system_actions: &Vec<SystemAction>,
+    previous_actions: &Vec<SystemAction>,
+    accepts: F,
+) -> Vec<SystemAction>
+where
+    F: Fn(&SystemAction) -> bool,
+{
+    return next_system_actions
+        .iter()
+        .filter(|sysaction| accepts(sysaction))
+        .chain(
+            previous_actions
+                .iter()
+                .filter(|sysaction| !accepts(sysaction)),
+        )
+        .cloned()
+        .collect();
+}
+
+pub fn build_action_diff(
+    next_system_actions: &Vec<SystemAction>,
+    full: bool,
+    root: Option<&Path>,
+) -> Result<Vec<SystemAction>> {
+    let previous_lockfile = read_lockfile(root)?;
+    return Ok(diff_actions(
+        previous_lockfile.as_ref().map(|lockfile| &lockfile.actions),
+        next_system_actions,
+        full,
+        &previous_lockfile
+            .as_ref()
+            .map(|lockfile| lockfile.hashes.clone())
+            .unwrap_or_default(),
+    ));
+}
+
+/// A copy already made whose source changed since the hash recorded by the previous installation
+fn is_changed_copy(sysaction: &SystemAction, previous_hashes: &BTreeMap<String, String>) -> bool {
+    return match sysaction {
+        SystemAction::File {
+            operation: FileOperation::Copy,
+            src,
+            dest,
+            origin,
+            ..
+        } => previous_hashes
+            .get(dest)
+            .is_some_and(|hash| checksum_file(&pathbuf![origin, src]).as_ref() != Some(hash)),
+        _ => false,
+    };
+}
+
+fn diff_actions(
+    previous_actions: Option<&Vec<SystemAction>>,
+    next_system_actions: &Vec<SystemAction>,
+    full: bool,
+    previous_hashes: &BTreeMap<String, String>,
+) -> Vec<SystemAction> {
+    let previous_actions = match previous_actions {
+        Some(previous_actions) => previous_actions,
+        None => {
+            return unique_packages(
+                next_system_actions
+                    .iter()
+                    .filter(|sysaction| is_runnable(sysaction))
+                    .cloned()
+                    .collect(),
+            )
+        }
+    };
+
+    // A package is uninstalled only when no remaining dot package wants it
+    let missing_last_actions = make_difference(previous_actions, next_system_actions);
+    let next_destinations = file_destinations(next_system_actions);
+    let delete_actions: Vec<SystemAction> =
+        unique_packages(inverse_additive_actions(&missing_last_actions))
+            .into_iter()
+            .map(|sysaction| match sysaction {
+                // The destination is still managed, keep its backup for later
+                SystemAction::File {
+                    operation,
+                    src,
+                    dest,
+                    origin,
+                    checksum,
+                    backup: _,
+                    escalate,
+                } if next_destinations.contains(&&dest) => SystemAction::File {
+                    operation,
+                    src,
+                    dest,
+                    origin,
+                    checksum,
+                    backup: None,
+                    escalate,
+                },
+                _ => sysaction,
+            })
+            .collect();
+    let mut delete_actions =
+        wrap_with_uninstall_scripts(delete_actions, previous_actions, next_system_actions);
+    let mut needed_actions: Vec<SystemAction> = unique_packages(
+        if full {
+            next_system_actions.clone()
+        } else {
+            next_system_actions
+                .iter()
+                .filter(|sysaction| {
+                    !previous_actions
+                        .iter()
+                        .any(|other| is_same_action(sysaction, other))
+                        || is_changed_copy(sysaction, previous_hashes)
+                })
+                .cloned()
+                .collect()
+        }
+        .into_iter()
+        .filter(is_runnable)
+        .collect(),
+    );
+    let mut all_actions = vec![];
+    all_actions.append(&mut delete_actions);
+    all_actions.append(&mut needed_actions);
+    return all_actions;
+}
+
+fn package_key(source: &String, name: &String) -> String {
+    return format!("{}:{}", source, name);
+}
+
+/// Drop the installs of packages already on the system and the uninstalls of
+/// packages that were there before dotstrap, returns the next preinstalled set
+pub fn skip_preinstalled_packages<F>(
+    all_actions: Vec<SystemAction>,
+    next_system_actions: &Vec<SystemAction>,
+    previous_lockfile: Option<&Lockfile>,
+    mut is_installed: F,
+) -> Result<(Vec<SystemAction>, BTreeSet<String>)>
+where
+    F: FnMut(&String, &String) -> Result<Option<bool>>,
+{
+    let empty = BTreeSet::new();
+    let previous_preinstalled = previous_lockfile
+        .map(|lockfile| &lockfile.preinstalled)
+        .unwrap_or(&empty);
+    let previously_owned: BTreeSet<String> = previous_lockfile
+        .map(|lockfile| lockfile.actions.iter())
+        .into_iter()
+        .flatten()
+        .filter_map(|sysaction| match sysaction {
+            SystemAction::Package {
+                operation: PackageOperation::Install,
+                source,
+                name,
+                origin: _,
+            } => Some(package_key(source, name)),
+            _ => None,
+        })
+        .filter(|key| !previous_preinstalled.contains(key))
+        .collect();
+    let mut preinstalled = previous_preinstalled.clone();
+    let mut kept_actions = vec![];
+    for sysaction in all_actions.into_iter() {
+        if let SystemAction::Package {
+            operation,
+            source,
+            name,
+            origin: _,
+        } = &sysaction
+        {
+            let key = package_key(source, name);
+            match operation {
+                PackageOperation::Install => {
+                    if is_installed(source, name)? == Some(true) {
+                        if !previously_owned.contains(&key) {
+                            preinstalled.insert(key);
+                        }
+                        continue;
+                    }
+                    preinstalled.remove(&key);
+                }
+                PackageOperation::Uninstall => {
+                    if previous_preinstalled.contains(&key) {
+                        continue;
+                    }
+                }
+            }
+        }
+        kept_actions.push(sysaction);
+    }
+    // Forget the packages which are not wanted anymore
+    let next_packages: BTreeSet<String> = next_system_actions
+        .iter()
+        .filter_map(|sysaction| match sysaction {
+            SystemAction::Package { source, name, .. } => Some(package_key(source, name)),
+            _ => None,
+        })
+        .collect();
+    preinstalled.retain(|key| next_packages.contains(key));
+    return Ok((kept_actions, preinstalled));
+}
+
+/// Whether an executed action, possibly compacted, stands for a planned one
+fn is_outcome_of(executed: &SystemAction, planned: &SystemAction) -> bool {
+    return match (executed, planned) {
+        (
+            SystemAction::Package {
+                operation: executed_operation,
+                source: executed_source,
+                name: executed_names,
+                origin: _,
+            },
+            SystemAction::Package {
+                operation,
+                source,
+                name,
+                origin: _,
+            },
+        ) => {
+            executed_operation == operation
+                && executed_source == source
+                && executed_names
+                    .split(' ')
+                    .any(|executed_name| executed_name == name)
+        }
+        (
+            SystemAction::Script {
+                operation: executed_operation,
+                script: executed_script,
+                origin: executed_origin,
+                ..
+            },
+            SystemAction::Script {
+                operation,
+                script,
+                origin,
+                ..
+            },
+        ) => {
+            executed_operation == operation
+                && executed_script == script
+                && executed_origin == origin
+        }
+        (
+            SystemAction::File {
+                operation: executed_operation,
+                src: executed_src,
+                dest: executed_dest,
+                origin: executed_origin,
+                ..
+            },
+            SystemAction::File {
+                operation,
+                src,
+                dest,
+                origin,
+                ..
+            },
+        ) => {
+            executed_operation == operation
+                && executed_src == src
+                && executed_dest == dest
+                && executed_origin == origin
+        }
+        _ => false,
+    };
+}
+
+/// Actions of the next lockfile once the plan ran: the next actions which were
+/// applied and the previous ones which could not be removed
+pub fn applied_actions(
+    next_system_actions: &Vec<SystemAction>,
+    previous_actions: &Vec<SystemAction>,
+    not_applied: &Vec<SystemAction>,
+) -> Vec<SystemAction> {
+    let is_applied = |planned: &SystemAction| {
+        !not_applied
+            .iter()
+            .any(|executed| is_outcome_of(executed, planned))
+    };
+    // Actions already applied by the previous installation are still there
+    let mut actions: Vec<SystemAction> = next_system_actions
+        .iter()
+        .filter(|sysaction| {
+            is_applied(sysaction)
+                || previous_actions
+                    .iter()
+                    .any(|previous| is_same_action(previous, sysaction))
+        })
+        .cloned()
+        .collect();
+    let applied_destinations = file_destinations(&actions);
+    let mut kept_back: Vec<SystemAction> = previous_actions
+        .iter()
+        .filter(|sysaction| {
+            !inverse_additive_actions(&vec![(*sysaction).clone()])
+                .iter()
+                .all(is_applied)
+        })
+        .filter(|sysaction| match sysaction {
+            SystemAction::File { dest, .. } => !applied_destinations.contains(&dest),
+            _ => true,
+        })
+        .cloned()
+        .collect();
+    // The uninstall scripts have to run again with the remaining removals
+    let kept_back_packages = package_paths(&kept_back);
+    kept_back.extend(
+        previous_actions
+            .iter()
+            .filter(|sysaction| {
+                matches!(
+                    sysaction,
+                    SystemAction::Script {
+                        operation: ScriptOperation::OnUninstall,
+                        ..
+                    }
+                ) && kept_back_packages.contains(&package_path(sysaction))
+                    && !next_system_actions.contains(sysaction)
+            })
+            .cloned()
+            .collect::<Vec<SystemAction>>(),
+    );
+    actions.append(&mut kept_back);
+    return actions;
+}
+
+/// Actions undoing the applied ones, paired with the action they undo, latest first.
+/// Removals are undone by applying again the previous action, scripts cannot be undone.
+pub fn rollback_actions(
+    applied: &Vec<SystemAction>,
+    previous_actions: &Vec<SystemAction>,
+    created_backups: &BTreeMap<String, String>,
+) -> Vec<(SystemAction, SystemAction)> {
+    let mut rollback = vec![];
+    for sysaction in applied.iter().rev() {
+        let undo = match sysaction {
+            SystemAction::Package {
+                operation: PackageOperation::Uninstall,
+                ..
+            }
+            | SystemAction::File {
+                operation: FileOperation::Remove,
+                ..
+            } => previous_actions
+                .iter()
+                .find(|previous| {
+                    inverse_additive_actions(&vec![(*previous).clone()])
+                        .iter()
+                        .any(|removal| is_outcome_of(sysaction, removal))
+                })
+                .cloned(),
+            // Reapplied as it was before, nothing to undo
+            SystemAction::File { .. }
+                if previous_actions
+                    .iter()
+                    .any(|previous| is_outcome_of(sysaction, previous)) =>
+            {
+                None
+            }
+            SystemAction::Package {
+                operation,
+                source,
+                name,
+                origin,
+            } => {
+                let installed_by_this_run: Vec<&str> = name
+                    .split(' ')
+                    .filter(|single_name| {
+                        !previous_actions.iter().any(|previous| {
+                            is_outcome_of(
+                                &SystemAction::Package {
+                                    operation: operation.clone(),
+                                    source: source.clone(),
+                                    name: single_name.to_string(),
+                                    origin: origin.clone(),
+                                },
+                                previous,
+                            )
+                        })
+                    })
+                    .collect();
+                if installed_by_this_run.is_empty() {
+                    None
+                } else {
+                    inverse_additive_actions(&vec![SystemAction::Package {
+                        operation: operation.clone(),
+                        source: source.clone(),
+                        name: installed_by_this_run.join(" "),
+                        origin: origin.clone(),
+                    }])
+                    .into_iter()
+                    .next()
+                }
+            }
+            SystemAction::File { .. } => {
+                inverse_additive_actions(&vec![sysaction.clone()])
+                    .into_iter()
+                    .next()
+                    .map(|undo| match undo {
+                        // Put back the file replaced during this run
+                        SystemAction::File {
+                            operation,
+                            src,
+                            dest,
+                            origin,
+                            checksum,
+                            backup: _,
+                            escalate,
+                        } => SystemAction::File {
+                            backup: created_backups.get(&dest).cloned(),
+                            operation,
+                            src,
+                            dest,
+                            origin,
+                            checksum,
+                            escalate,
+                        },
+                        _ => undo,
+                    })
+            }
+            SystemAction::Script { .. } => None,
+        };
+        if let Some(undo) = undo {
+            rollback.push((sysaction.clone(), undo));
+        }
+    }
+    return rollback;
+}
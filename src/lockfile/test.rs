@@ -0,0 +1,530 @@
+use super::*;
+use std::collections::HashMap;
+
+#[test]
+fn test_lockfile_migration_from_action_list() {
+    let content = "- type: Package
+  operation: Install
+  source: os
+  name: tmux
+  origin: packages/tmux
+- type: File
+  operation: Link
+  src: tmux.conf
+  dest: /home/user/.tmux.conf
+  origin: packages/tmux
+";
+    let lockfile = Lockfile::parse(content).unwrap();
+    assert_eq!(LOCKFILE_VERSION, lockfile.version);
+    assert_eq!(
+        vec![
+            SystemAction::Package {
+                operation: PackageOperation::Install,
+                source: "os".to_string(),
+                name: "tmux".to_string(),
+                origin: "packages/tmux".to_string(),
+            },
+            SystemAction::File {
+                operation: FileOperation::Link,
+                src: "tmux.conf".to_string(),
+                dest: "/home/user/.tmux.conf".to_string(),
+                origin: "packages/tmux".to_string(),
+                checksum: None,
+                backup: None,
+                escalate: false,
+            },
+        ],
+        lockfile.actions
+    );
+
+    let serialized = serde_yaml::to_string(&lockfile).unwrap();
+    assert_eq!(lockfile, Lockfile::parse(&serialized).unwrap());
+    assert!(Lockfile::parse("version: 999\nactions: []").is_err());
+}
+
+#[test]
+fn test_uninstall_scripts_surround_removed_package() {
+    let previous_actions = vec![
+        SystemAction::Package {
+            operation: PackageOperation::Install,
+            source: "os".to_string(),
+            name: "docker".to_string(),
+            origin: "packages/docker".to_string(),
+        },
+        SystemAction::Script {
+            operation: ScriptOperation::Run,
+            script: "systemctl enable docker".to_string(),
+            origin: "packages/docker:post_install".to_string(),
+            policy: RunPolicy::OnChange,
+            checksum: None,
+            interactive: false,
+            escalate: false,
+        },
+        SystemAction::Script {
+            operation: ScriptOperation::OnUninstall,
+            script: "systemctl disable docker".to_string(),
+            origin: "packages/docker:pre_uninstall".to_string(),
+            policy: RunPolicy::OnChange,
+            checksum: None,
+            interactive: false,
+            escalate: false,
+        },
+    ];
+    let delete_actions = inverse_additive_actions(&previous_actions);
+    let ordered_actions = wrap_with_uninstall_scripts(delete_actions, &previous_actions, &vec![]);
+    assert_eq!(
+        vec![
+            SystemAction::Script {
+                operation: ScriptOperation::Run,
+                script: "systemctl disable docker".to_string(),
+                origin: "packages/docker:pre_uninstall".to_string(),
+                policy: RunPolicy::Always,
+                checksum: None,
+                interactive: false,
+                escalate: false,
+            },
+            SystemAction::Package {
+                operation: PackageOperation::Uninstall,
+                source: "os".to_string(),
+                name: "docker".to_string(),
+                origin: "packages/docker".to_string(),
+            },
+        ],
+        ordered_actions
+    );
+}
+
+#[test]
+fn test_script_run_policies() {
+    let script = |script: &str, policy: RunPolicy| SystemAction::Script {
+        operation: ScriptOperation::Run,
+        script: script.to_string(),
+        origin: "packages/zsh:post_install".to_string(),
+        policy,
+        checksum: None,
+        interactive: false,
+        escalate: false,
+    };
+    assert!(is_same_action(
+        &script("chsh -s /bin/zsh", RunPolicy::Once),
+        &script("# edited\nchsh -s /bin/zsh", RunPolicy::Once)
+    ));
+    assert!(!is_same_action(
+        &script("chsh -s /bin/zsh", RunPolicy::OnChange),
+        &script("# edited\nchsh -s /bin/zsh", RunPolicy::OnChange)
+    ));
+    assert!(!is_same_action(
+        &script("chsh -s /bin/zsh", RunPolicy::Always),
+        &script("chsh -s /bin/zsh", RunPolicy::Always)
+    ));
+}
+
+#[test]
+fn test_preinstalled_packages_are_left_alone() {
+    let package = |operation: PackageOperation, name: &str| SystemAction::Package {
+        operation,
+        source: "os".to_string(),
+        name: name.to_string(),
+        origin: "packages/base".to_string(),
+    };
+    // git was on the system before dotstrap, tmux was installed by it
+    let (actions, preinstalled) = skip_preinstalled_packages(
+        vec![
+            package(PackageOperation::Install, "git"),
+            package(PackageOperation::Install, "tmux"),
+        ],
+        &vec![
+            package(PackageOperation::Install, "git"),
+            package(PackageOperation::Install, "tmux"),
+        ],
+        None,
+        |_, name| Ok(Some(name == "git")),
+    )
+    .unwrap();
+    assert_eq!(vec![package(PackageOperation::Install, "tmux")], actions);
+    assert_eq!(BTreeSet::from(["os:git".to_string()]), preinstalled);
+
+    let mut lockfile = Lockfile::new(
+        "test",
+        vec![
+            package(PackageOperation::Install, "git"),
+            package(PackageOperation::Install, "tmux"),
+        ],
+    )
+    .unwrap();
+    lockfile.preinstalled = preinstalled;
+    let (actions, preinstalled) = skip_preinstalled_packages(
+        vec![
+            package(PackageOperation::Uninstall, "git"),
+            package(PackageOperation::Uninstall, "tmux"),
+        ],
+        &vec![],
+        Some(&lockfile),
+        |_, _| Ok(Some(true)),
+    )
+    .unwrap();
+    assert_eq!(vec![package(PackageOperation::Uninstall, "tmux")], actions);
+    assert!(preinstalled.is_empty());
+}
+
+#[test]
+fn test_packages_are_diffed_regardless_of_origin() {
+    let package = |operation: PackageOperation, origin: &str| SystemAction::Package {
+        operation,
+        source: "os".to_string(),
+        name: "ripgrep".to_string(),
+        origin: origin.to_string(),
+    };
+    let both = vec![
+        package(PackageOperation::Install, "packages/base"),
+        package(PackageOperation::Install, "packages/dev"),
+    ];
+    let moved = vec![package(PackageOperation::Install, "packages/dev")];
+    assert_eq!(
+        vec![package(PackageOperation::Install, "packages/base")],
+        diff_actions(None, &both, false, &BTreeMap::new())
+    );
+    assert!(diff_actions(Some(&both), &moved, false, &BTreeMap::new()).is_empty());
+    assert!(diff_actions(Some(&moved), &both, false, &BTreeMap::new()).is_empty());
+    assert_eq!(
+        vec![package(PackageOperation::Uninstall, "packages/dev")],
+        diff_actions(Some(&both), &vec![], false, &BTreeMap::new())
+    );
+}
+
+#[test]
+fn test_lockfile_records_applied_actions() {
+    let package = |name: &str| SystemAction::Package {
+        operation: PackageOperation::Install,
+        source: "os".to_string(),
+        name: name.to_string(),
+        origin: format!("packages/{}", name),
+    };
+    let link = |dest: &str, origin: &str| SystemAction::File {
+        operation: FileOperation::Link,
+        src: "rc".to_string(),
+        dest: dest.to_string(),
+        origin: origin.to_string(),
+        checksum: None,
+        backup: None,
+        escalate: false,
+    };
+    let previous_actions = vec![link("/home/user/.oldrc", "packages/old")];
+    let next_system_actions = vec![
+        package("git"),
+        link("/home/user/.gitconfig", "packages/git"),
+        package("tmux"),
+    ];
+    let removal = inverse_additive_actions(&previous_actions);
+    // git failed, its link was skipped and so was the removal of the old package
+    let not_applied = vec![
+        package("git"),
+        link("/home/user/.gitconfig", "packages/git"),
+        removal[0].clone(),
+    ];
+    assert_eq!(
+        vec![package("tmux"), link("/home/user/.oldrc", "packages/old")],
+        applied_actions(&next_system_actions, &previous_actions, &not_applied)
+    );
+    assert_eq!(
+        next_system_actions,
+        applied_actions(&next_system_actions, &previous_actions, &vec![])
+    );
+}
+
+#[test]
+fn test_rollback_undoes_applied_actions() {
+    let package = |operation: PackageOperation, name: &str| SystemAction::Package {
+        operation,
+        source: "os".to_string(),
+        name: name.to_string(),
+        origin: "packages/base".to_string(),
+    };
+    let file = |operation: FileOperation, dest: &str, backup: Option<&str>| SystemAction::File {
+        src: if operation == FileOperation::Remove {
+            String::new()
+        } else {
+            "rc".to_string()
+        },
+        operation,
+        dest: dest.to_string(),
+        origin: "packages/base".to_string(),
+        checksum: None,
+        backup: backup.map(|backup| backup.to_string()),
+        escalate: false,
+    };
+    let previous_actions = vec![
+        package(PackageOperation::Install, "git"),
+        file(FileOperation::Link, "/home/user/.oldrc", None),
+    ];
+    let applied = vec![
+        file(FileOperation::Remove, "/home/user/.oldrc", None),
+        package(PackageOperation::Install, "git tmux"),
+        file(FileOperation::Link, "/home/user/.newrc", None),
+    ];
+    let created_backups = BTreeMap::from([(
+        "/home/user/.newrc".to_string(),
+        "/backups/home/user/.newrc.1".to_string(),
+    )]);
+    assert_eq!(
+        vec![
+            (
+                applied[2].clone(),
+                file(
+                    FileOperation::Remove,
+                    "/home/user/.newrc",
+                    Some("/backups/home/user/.newrc.1")
+                )
+            ),
+            (
+                applied[1].clone(),
+                package(PackageOperation::Uninstall, "tmux")
+            ),
+            (applied[0].clone(), previous_actions[1].clone()),
+        ],
+        rollback_actions(&applied, &previous_actions, &created_backups)
+    );
+}
+
+#[test]
+fn test_filtered_plan() {
+    let package = |name: &str| SystemAction::Package {
+        operation: PackageOperation::Install,
+        source: "os".to_string(),
+        name: name.to_string(),
+        origin: "hosts/desktop".to_string(),
+    };
+    let link = |dest: &str| SystemAction::File {
+        operation: FileOperation::Link,
+        src: "config".to_string(),
+        dest: dest.to_string(),
+        origin: "hosts/desktop".to_string(),
+        checksum: None,
+        backup: None,
+        escalate: false,
+    };
+    let previous_actions = vec![package("git"), link("/home/user/.oldrc")];
+    let next_actions = vec![package("tmux"), link("/home/user/.newrc")];
+    // Only the files, the packages stay as they were
+    let is_file = |sysaction: &SystemAction| matches!(sysaction, SystemAction::File { .. });
+    let planned = filter_planned_actions(&next_actions, &previous_actions, is_file);
+    assert_eq!(vec![link("/home/user/.newrc"), package("git")], planned);
+    let mut removed_link = link("/home/user/.oldrc");
+    if let SystemAction::File { operation, src, .. } = &mut removed_link {
+        *operation = FileOperation::Remove;
+        *src = String::new();
+    }
+    assert_eq!(
+        vec![removed_link, link("/home/user/.newrc")],
+        diff_actions(Some(&previous_actions), &planned, false, &BTreeMap::new())
+    );
+    assert_eq!(
+        planned,
+        applied_actions(&planned, &previous_actions, &vec![])
+    );
+}
+
+#[test]
+fn test_copies_follow_their_source() {
+    let origin = std::env::temp_dir().join(format!("dotstrap-test-copy-{}", std::process::id()));
+    std::fs::create_dir_all(&origin).unwrap();
+    std::fs::write(origin.join("profile"), "first").unwrap();
+    let copy = |dest: &str| SystemAction::File {
+        operation: FileOperation::Copy,
+        src: "profile".to_string(),
+        dest: dest.to_string(),
+        origin: origin.to_string_lossy().to_string(),
+        checksum: None,
+        backup: None,
+        escalate: false,
+    };
+    let actions = vec![copy("/home/user/.profile"), copy("/home/user/.unknown")];
+    let mut lockfile = Lockfile::new("desktop", actions.clone()).unwrap();
+    // The hash of a copy installed by an older version is unknown
+    lockfile.hashes.remove("/home/user/.unknown");
+    assert!(diff_actions(Some(&actions), &actions, false, &lockfile.hashes).is_empty());
+
+    std::fs::write(origin.join("profile"), "second").unwrap();
+    let diff = diff_actions(Some(&actions), &actions, false, &lockfile.hashes);
+    std::fs::remove_dir_all(&origin).unwrap();
+    assert_eq!(vec![copy("/home/user/.profile")], diff);
+}
+
+#[test]
+fn test_tree_links_follow_the_package() {
+    let directory = std::env::temp_dir().join(format!("dotstrap-test-tree-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(directory.join("nvim").join("lua")).unwrap();
+    std::fs::write(directory.join("nvim").join("init.lua"), "").unwrap();
+    std::fs::write(directory.join("nvim").join("lua").join("keys.lua"), "").unwrap();
+    let mut package: crate::package::PackageDefinition = serde_yaml::from_str(
+        "links:\n  - src: nvim\n    dest: /home/user/.config/nvim\n    mode: tree",
+    )
+    .unwrap();
+    package.path = directory.to_string_lossy().to_string();
+    let context = crate::variables::VariableContext::new(
+        HashMap::new(),
+        HashMap::new(),
+        &package,
+        &HashMap::new(),
+    );
+    let transform = || {
+        return crate::action::transform_host_to_actions(&package, &Default::default(), &context)
+            .unwrap();
+    };
+    let link = |file: &str| SystemAction::File {
+        operation: FileOperation::Link,
+        src: format!("nvim/{}", file),
+        dest: format!("/home/user/.config/nvim/{}", file),
+        origin: package.path.clone(),
+        checksum: None,
+        backup: None,
+        escalate: false,
+    };
+
+    // Every file of the directory is linked on its own
+    let previous = transform();
+    assert_eq!(vec![link("init.lua"), link("lua/keys.lua")], previous);
+
+    // A file deleted from the package is unlinked on the next install
+    std::fs::remove_file(directory.join("nvim").join("lua").join("keys.lua")).unwrap();
+    let next = transform();
+    assert_eq!(vec![link("init.lua")], next);
+    assert_eq!(
+        vec![SystemAction::File {
+            operation: FileOperation::Remove,
+            src: String::new(),
+            dest: "/home/user/.config/nvim/lua/keys.lua".to_string(),
+            origin: package.path.clone(),
+            checksum: None,
+            backup: None,
+            escalate: false,
+        }],
+        diff_actions(Some(&previous), &next, false, &BTreeMap::new())
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_first_backup_survives_reinstalls() {
+    use crate::action::execution::{execute, ExecutionOptions, OutputMode};
+    use crate::backup::BackupStore;
+
+    let directory =
+        std::env::temp_dir().join(format!("dotstrap-test-reinstall-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    let package = directory.join("packages").join("shell");
+    let home = directory.join("home");
+    std::fs::create_dir_all(&package).unwrap();
+    std::fs::create_dir_all(&home).unwrap();
+    std::fs::write(home.join("profile"), "original profile").unwrap();
+    std::fs::write(home.join("inputrc"), "original inputrc").unwrap();
+    let origin = package.to_string_lossy().to_string();
+    let file = |operation: FileOperation, name: &str, content: &str| {
+        std::fs::write(package.join(name), content).unwrap();
+        let checksum = match operation {
+            FileOperation::Template => Some(checksum(content.as_bytes())),
+            _ => None,
+        };
+        return SystemAction::File {
+            operation,
+            src: name.to_string(),
+            dest: home.join(name).to_string_lossy().to_string(),
+            origin: origin.clone(),
+            checksum,
+            backup: None,
+            escalate: false,
+        };
+    };
+    // One install run: apply the difference and record the next lockfile
+    let config: crate::host::config::HostConfiguration = serde_yaml::from_str("{}").unwrap();
+    let install = |actions: Vec<SystemAction>, previous: &Option<Lockfile>| {
+        let (previous_actions, previous_hashes, written) = match previous {
+            Some(lockfile) => (
+                lockfile.actions.clone(),
+                lockfile.hashes.clone(),
+                lockfile.written_checksums(),
+            ),
+            None => (vec![], BTreeMap::new(), BTreeMap::new()),
+        };
+        let mut store = BackupStore {
+            root: directory.join("backups"),
+            created: BTreeMap::new(),
+        };
+        let report = execute(
+            &diff_actions(
+                previous.as_ref().map(|_| &previous_actions),
+                &actions,
+                false,
+                &previous_hashes,
+            ),
+            &config,
+            &Default::default(),
+            &mut store,
+            &ExecutionOptions {
+                dry_run: false,
+                output: OutputMode::Quiet,
+                keep_going: false,
+                dependencies: HashMap::new(),
+                jobs: 1,
+                written,
+            },
+        );
+        assert!(!report.has_failures());
+        return Some(
+            Lockfile::new(
+                "test",
+                attach_backups(&actions, &previous_actions, &store.created),
+            )
+            .unwrap(),
+        );
+    };
+
+    let lockfile = install(
+        vec![
+            file(FileOperation::Copy, "profile", "first profile"),
+            file(FileOperation::Template, "inputrc", "first inputrc"),
+        ],
+        &None,
+    );
+    let lockfile = install(
+        vec![
+            file(FileOperation::Copy, "profile", "second profile"),
+            file(FileOperation::Template, "inputrc", "second inputrc"),
+        ],
+        &lockfile,
+    );
+    assert_eq!(
+        "second profile",
+        std::fs::read_to_string(home.join("profile")).unwrap()
+    );
+    assert_eq!(
+        "second inputrc",
+        std::fs::read_to_string(home.join("inputrc")).unwrap()
+    );
+    // Removed from the configuration, the files found at first come back
+    install(vec![], &lockfile);
+    assert_eq!(
+        "original profile",
+        std::fs::read_to_string(home.join("profile")).unwrap()
+    );
+    assert_eq!(
+        "original inputrc",
+        std::fs::read_to_string(home.join("inputrc")).unwrap()
+    );
+
+    // A later backup of the same destination does not replace the first one
+    let mut recorded = file(FileOperation::Copy, "profile", "second profile");
+    if let SystemAction::File { backup, .. } = &mut recorded {
+        *backup = Some("first".to_string());
+    }
+    let created = BTreeMap::from([(
+        home.join("profile").to_string_lossy().to_string(),
+        "second".to_string(),
+    )]);
+    assert_eq!(
+        vec![recorded.clone()],
+        attach_backups(&vec![recorded.clone()], &vec![recorded], &created)
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -1,16 +1,23 @@
+use action::filter::ActionFilter;
 use anyhow::Result;
 use clap::Parser;
 use commands::add::{run_add, RunAddOptions};
+use commands::backups::{run_backups, BackupsAction, RunBackupsOptions};
 use commands::generate::{run_generate, RunGenerateOptions};
 use commands::init::{run_init, RunInitOptions};
 use commands::install::{run_install, RunInstallOptions};
 use commands::list::{run_list, RunListOptions};
 use commands::remove::{run_remove, RunRemoveOptions};
+use commands::status::{run_status, RunStatusOptions};
+use commands::sync::{run_sync, RunSyncOptions};
+use commands::validate::{run_validate, RunValidateOptions};
 use std::fs;
 
 mod action;
+mod backup;
 mod cli;
 mod commands;
+mod condition;
 #[cfg(feature = "git")]
 mod git;
 mod helpers;
@@ -18,6 +25,8 @@ mod host;
 mod lockfile;
 mod package;
 mod resolver;
+mod validation;
+mod variables;
 
 fn main() -> Result<()> {
     let args = cli::Args::parse();
@@ -66,19 +75,114 @@ fn main() -> Result<()> {
         Some(cli::Action::List {}) => {
             run_list(machine_hostname, RunListOptions {})?;
         }
-        Some(cli::Action::Generate { hostname, full }) => {
+        Some(cli::Action::Validate { hostname }) => {
+            let hostname = hostname.unwrap_or(machine_hostname);
+            run_validate(hostname, RunValidateOptions {})?;
+        }
+        Some(cli::Action::Status { hostname, root }) => {
+            let hostname = hostname.unwrap_or(machine_hostname);
+            run_status(hostname, RunStatusOptions { root })?;
+        }
+        Some(cli::Action::Generate {
+            hostname,
+            full,
+            root,
+            system,
+            user,
+            skip,
+            bundle,
+        }) => {
             let hostname = hostname.unwrap_or(machine_hostname);
             // Load host definition and prepare system actions from it
-            run_generate(hostname, RunGenerateOptions { full })?;
+            run_generate(
+                hostname,
+                RunGenerateOptions {
+                    full,
+                    root,
+                    filter: ActionFilter { system, user, skip },
+                    bundle,
+                },
+            )?;
         }
         Some(cli::Action::Install {
             hostname,
             dry,
             full,
             lock,
+            keep_going,
+            atomic,
+            quiet,
+            jobs,
+            repo,
+            root,
+            system,
+            user,
+            skip,
+        }) => {
+            let hostname = hostname.unwrap_or(machine_hostname);
+            run_install(
+                hostname,
+                RunInstallOptions {
+                    dry,
+                    full,
+                    lock,
+                    keep_going,
+                    atomic,
+                    quiet,
+                    jobs: jobs as usize,
+                    repo,
+                    root,
+                    filter: ActionFilter { system, user, skip },
+                    show_plan: false,
+                },
+            )?;
+        }
+        Some(cli::Action::Sync {
+            hostname,
+            dry,
+            full,
+            lock,
+            keep_going,
+            atomic,
+            quiet,
+            jobs,
+            root,
+            system,
+            user,
+            skip,
         }) => {
             let hostname = hostname.unwrap_or(machine_hostname);
-            run_install(hostname, RunInstallOptions { dry, full, lock })?;
+            run_sync(
+                hostname,
+                RunSyncOptions {
+                    install: RunInstallOptions {
+                        dry,
+                        full,
+                        lock,
+                        keep_going,
+                        atomic,
+                        quiet,
+                        jobs: jobs as usize,
+                        repo: None,
+                        root,
+                        filter: ActionFilter { system, user, skip },
+                        show_plan: true,
+                    },
+                },
+            )?;
+        }
+        Some(cli::Action::Backups { action }) => {
+            let (hostname, action) = match action {
+                cli::BackupsAction::List { hostname } => (hostname, BackupsAction::List),
+                cli::BackupsAction::Restore { dest, hostname } => {
+                    (hostname, BackupsAction::Restore { dest })
+                }
+                cli::BackupsAction::Prune { hostname, all } => {
+                    (hostname, BackupsAction::Prune { all })
+                }
+            };
+            let hostname = hostname.unwrap_or(machine_hostname);
+            run_backups(hostname, RunBackupsOptions { action })?;
         }
         None => {}
     }
@@ -1,6 +1,6 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use pathbuf::pathbuf;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use serde_with::{serde_as, skip_serializing_none, DisplayFromStr, PickFirst};
 use std::fmt::{self, Display};
 use std::str::FromStr;
@@ -10,11 +10,34 @@ fn dependency_default_source() -> String {
     return "os".to_string();
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct DependencyDefinition {
     #[serde(default = "dependency_default_source")]
     pub source: String,
     pub name: String,
+    pub when: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ConditionalDependency<'a> {
+    source: &'a String,
+    name: &'a String,
+    when: &'a String,
+}
+
+impl Serialize for DependencyDefinition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        // Keep the short "source:name" notation unless a condition is attached
+        return match &self.when {
+            Some(when) => ConditionalDependency {
+                source: &self.source,
+                name: &self.name,
+                when,
+            }
+            .serialize(serializer),
+            None => serializer.serialize_str(&self.to_string()),
+        };
+    }
 }
 
 impl Display for DependencyDefinition {
@@ -39,16 +62,42 @@ impl FromStr for DependencyDefinition {
             }
             .to_string(),
             name: splitted[splitted.len() - 1].to_string(),
+            when: None,
         });
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// Link the src path itself, even if it is a directory
+    #[default]
+    Single,
+    /// Mirror the src directory at dest and link each file individually
+    Tree,
+}
+
+impl LinkMode {
+    fn is_single(&self) -> bool {
+        return LinkMode::Single.eq(self);
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
 pub struct LinkFileDefinition {
     pub src: String,
     pub dest: String,
     #[serde(default)]
     pub copy: bool,
+    #[serde(default)]
+    pub template: bool,
+    #[serde(default, skip_serializing_if = "LinkMode::is_single")]
+    pub mode: LinkMode,
+    pub when: Option<String>,
+    /// Create the file with the escalation tool, for destinations owned by root
+    #[serde(default, rename = "become", skip_serializing_if = "std::ops::Not::not")]
+    pub escalate: bool,
 }
 
 impl Display for LinkFileDefinition {
@@ -69,13 +118,111 @@ impl FromStr for LinkFileDefinition {
             src: splitted[0].to_string(),
             dest: splitted[1].to_string(),
             copy: false,
+            template: false,
+            mode: LinkMode::Single,
+            when: None,
+            escalate: false,
+        });
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RunPolicy {
+    /// Run only the first time
+    Once,
+    /// Run again when the script or one of its watched files changes
+    #[default]
+    OnChange,
+    /// Run on every installation
+    Always,
+}
+
+impl RunPolicy {
+    pub fn is_onchange(&self) -> bool {
+        return RunPolicy::OnChange.eq(self);
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ScriptDefinition {
+    pub run: String,
+    pub when: Option<String>,
+    #[serde(default)]
+    pub policy: RunPolicy,
+    /// Files of the package triggering an onchange script when modified
+    #[serde(default)]
+    pub watch: Vec<String>,
+    /// Attach the script to the terminal, to answer its prompts
+    #[serde(default)]
+    pub interactive: bool,
+    /// Run the script with the escalation tool
+    #[serde(default, rename = "become")]
+    pub escalate: bool,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize)]
+struct DetailedScript<'a> {
+    run: &'a String,
+    when: &'a Option<String>,
+    #[serde(skip_serializing_if = "RunPolicy::is_onchange")]
+    policy: RunPolicy,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    watch: &'a Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    interactive: bool,
+    #[serde(rename = "become", skip_serializing_if = "std::ops::Not::not")]
+    escalate: bool,
+}
+
+impl Serialize for ScriptDefinition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        // Keep the plain string notation when there is nothing else than the script
+        if self.when.is_none()
+            && self.policy.is_onchange()
+            && self.watch.is_empty()
+            && !self.interactive
+            && !self.escalate
+        {
+            return serializer.serialize_str(&self.run);
+        }
+        return DetailedScript {
+            run: &self.run,
+            when: &self.when,
+            policy: self.policy,
+            watch: &self.watch,
+            interactive: self.interactive,
+            escalate: self.escalate,
+        }
+        .serialize(serializer);
+    }
+}
+
+impl Display for ScriptDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.run)
+    }
+}
+
+impl FromStr for ScriptDefinition {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        return Ok(ScriptDefinition {
+            run: value.to_string(),
+            when: None,
+            policy: RunPolicy::OnChange,
+            watch: vec![],
+            interactive: false,
+            escalate: false,
         });
     }
 }
 
 #[serde_as]
 #[skip_serializing_none]
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
 pub struct PackageDefinition {
     #[serde(default)]
     #[serde(skip)]
@@ -86,29 +233,55 @@ pub struct PackageDefinition {
 
     pub description: Option<String>,
 
-    pub pre_install: Option<String>,
+    /// Upstream project of the package
+    pub source: Option<String>,
+
+    /// Default variables of the package, overridden by the host ones
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
 
     #[serde(default)]
-    #[serde_as(as = "Vec<PickFirst<(DisplayFromStr, _)>>")]
+    #[serde_as(deserialize_as = "Option<PickFirst<(DisplayFromStr, _)>>")]
+    pub pre_install: Option<ScriptDefinition>,
+
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Vec<PickFirst<(DisplayFromStr, _)>>")]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub dependencies: Vec<DependencyDefinition>,
 
-    pub post_install: Option<String>,
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(DisplayFromStr, _)>>")]
+    pub post_install: Option<ScriptDefinition>,
 
     #[serde(default)]
     #[serde_as(as = "Vec<PickFirst<(_, DisplayFromStr)>>")]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub links: Vec<LinkFileDefinition>,
 
-    pub post_links: Option<String>,
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(DisplayFromStr, _)>>")]
+    pub post_links: Option<ScriptDefinition>,
+
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(DisplayFromStr, _)>>")]
+    pub pre_uninstall: Option<ScriptDefinition>,
+
+    #[serde(default)]
+    #[serde_as(deserialize_as = "Option<PickFirst<(DisplayFromStr, _)>>")]
+    pub post_uninstall: Option<ScriptDefinition>,
 }
 
 impl PackageDefinition {
     pub fn load(pathname: &Path) -> Result<PackageDefinition> {
-        let file_content = fs::read_to_string(pathname).expect(&format!(
+        let file_content = fs::read_to_string(pathname).context(format!(
             "Unable to find file {}",
             pathname.to_string_lossy()
-        ));
+        ))?;
+        return PackageDefinition::parse(pathname, &file_content);
+    }
+
+    pub fn parse(pathname: &Path, file_content: &str) -> Result<PackageDefinition> {
         let parentdir = pathname
             .parent()
             .unwrap()
@@ -117,7 +290,7 @@ impl PackageDefinition {
             .to_str()
             .unwrap()
             .to_string();
-        let mut package_definition: PackageDefinition = serde_yaml::from_str(&file_content)?;
+        let mut package_definition: PackageDefinition = serde_yaml::from_str(file_content)?;
         package_definition.name = parentdir;
         package_definition.path = pathname.parent().unwrap().to_str().unwrap().to_string();
         return Ok(package_definition);
@@ -0,0 +1,109 @@
+use anyhow::{bail, Context, Result};
+use pathbuf::pathbuf;
+use std::collections::HashMap;
+
+use crate::package::{PackageCollection, PackageDefinition};
+
+#[cfg(test)]
+mod test;
+
+/// Every dot package reachable from a host, each loaded once
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    pub packages: PackageCollection,
+    /// Package names ordered so that dependencies come before their dependents
+    pub order: Vec<String>,
+}
+
+impl DependencyGraph {
+    /// Paths of the dot packages each package directly depends on, host included
+    pub fn package_dependencies(&self, host: &PackageDefinition) -> HashMap<String, Vec<String>> {
+        return self
+            .packages
+            .values()
+            .chain([host])
+            .map(|package| {
+                let dependencies = dot_dependencies(package)
+                    .iter()
+                    .filter_map(|name| self.packages.get(name))
+                    .map(|dependency| dependency.path.clone())
+                    .collect();
+                (package.path.clone(), dependencies)
+            })
+            .collect();
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum VisitState {
+    Visiting,
+    Visited,
+}
+
+fn dot_dependencies(package: &PackageDefinition) -> Vec<String> {
+    return package
+        .dependencies
+        .iter()
+        .filter(|dependency| dependency.source == "dot")
+        .map(|dependency| dependency.name.clone())
+        .collect();
+}
+
+fn visit<F>(
+    name: &String,
+    load: &mut F,
+    graph: &mut DependencyGraph,
+    states: &mut HashMap<String, VisitState>,
+    path: &mut Vec<String>,
+) -> Result<()>
+where
+    F: FnMut(&str) -> Result<PackageDefinition>,
+{
+    match states.get(name) {
+        Some(VisitState::Visited) => return Ok(()),
+        Some(VisitState::Visiting) => {
+            let start = path.iter().position(|item| item == name).unwrap();
+            let cycle: Vec<String> = path[start..]
+                .iter()
+                .chain([name])
+                .map(|item| format!("dot:{}", item))
+                .collect();
+            bail!("Dependency cycle detected: {}", cycle.join(" -> "));
+        }
+        None => {}
+    }
+    states.insert(name.clone(), VisitState::Visiting);
+    path.push(name.clone());
+    let definition = load(name)?;
+    for dependency in dot_dependencies(&definition).iter() {
+        visit(dependency, load, graph, states, path)?;
+    }
+    path.pop();
+    states.insert(name.clone(), VisitState::Visited);
+    graph.order.push(name.clone());
+    graph.packages.insert(name.clone(), definition);
+    return Ok(());
+}
+
+/// Build the dependency graph of a package with a custom package loader
+pub fn resolve_with<F>(package: &PackageDefinition, mut load: F) -> Result<DependencyGraph>
+where
+    F: FnMut(&str) -> Result<PackageDefinition>,
+{
+    let mut graph = DependencyGraph::default();
+    let mut states = HashMap::<String, VisitState>::new();
+    for dependency in dot_dependencies(package).iter() {
+        visit(dependency, &mut load, &mut graph, &mut states, &mut vec![])?;
+    }
+    return Ok(graph);
+}
+
+pub fn resolve_dependencies(package: &PackageDefinition) -> Result<DependencyGraph> {
+    return resolve_with(package, |name| {
+        let package_pathname = pathbuf!["packages", name, "package.yml"];
+        return PackageDefinition::load(&package_pathname).context(format!(
+            "Unable to parse {}",
+            package_pathname.to_str().unwrap()
+        ));
+    });
+}
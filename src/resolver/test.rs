@@ -0,0 +1,49 @@
+use super::*;
+
+fn package(name: &str, content: &str) -> PackageDefinition {
+    let mut definition: PackageDefinition = serde_yaml::from_str(content).unwrap();
+    definition.name = name.to_string();
+    definition.path = format!("packages/{}", name);
+    return definition;
+}
+
+fn load_from<'a>(
+    repo: &'a HashMap<&'a str, &'a str>,
+) -> impl FnMut(&str) -> Result<PackageDefinition> + 'a {
+    return move |name| Ok(package(name, repo.get(name).unwrap()));
+}
+
+#[test]
+fn test_resolver_orders_diamond_dependencies() {
+    let repo = HashMap::from([
+        ("app", "dependencies: [dot:left, dot:right]"),
+        ("left", "dependencies: [dot:base]"),
+        ("right", "dependencies: [dot:base, tmux]"),
+        ("base", "dependencies: [git]"),
+    ]);
+    let mut loaded = vec![];
+    let mut load = load_from(&repo);
+    let graph = resolve_with(&package("host", "dependencies: [dot:app]"), |name| {
+        loaded.push(name.to_string());
+        return load(name);
+    })
+    .unwrap();
+    assert_eq!(vec!["base", "left", "right", "app"], graph.order);
+    assert_eq!(vec!["app", "left", "base", "right"], loaded);
+}
+
+#[test]
+fn test_resolver_detects_cycles() {
+    let repo = HashMap::from([
+        ("a", "dependencies: [dot:b]"),
+        ("b", "dependencies: [dot:c]"),
+        ("c", "dependencies: [dot:a]"),
+    ]);
+    let error = resolve_with(&package("host", "dependencies: [dot:a]"), load_from(&repo))
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        "Dependency cycle detected: dot:a -> dot:b -> dot:c -> dot:a",
+        error
+    );
+}
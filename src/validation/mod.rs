@@ -0,0 +1,422 @@
+use anyhow::Result;
+use pathbuf::pathbuf;
+use serde_yaml::Value;
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use crate::host::config::HostConfiguration;
+use crate::host::facts::gather_facts;
+use crate::host::profile::{profile_names, read_extends, PROFILES_DIRECTORY};
+use crate::host::HostDefinition;
+use crate::package::{PackageCollection, PackageDefinition};
+use crate::resolver;
+use crate::variables::{VariableContext, Variables};
+
+use self::position::Positions;
+
+mod position;
+#[cfg(test)]
+mod test;
+
+const PACKAGE_KEYS: &[&str] = &[
+    "description",
+    "source",
+    "vars",
+    "pre_install",
+    "dependencies",
+    "post_install",
+    "links",
+    "post_links",
+    "pre_uninstall",
+    "post_uninstall",
+];
+const SCRIPT_HOOKS: &[&str] = &[
+    "pre_install",
+    "post_install",
+    "post_links",
+    "pre_uninstall",
+    "post_uninstall",
+];
+const DEPENDENCY_KEYS: &[&str] = &["source", "name", "when"];
+const LINK_KEYS: &[&str] = &["src", "dest", "copy", "template", "mode", "when", "become"];
+const SCRIPT_KEYS: &[&str] = &["run", "when", "policy", "watch", "interactive", "become"];
+const CONFIG_KEYS: &[&str] = &["extends", "escalation", "package_managers", "vars"];
+const PACKAGE_MANAGER_KEYS: &[&str] = &["preset", "multiple", "interactive", "become", "commands"];
+const COMMANDS_KEYS: &[&str] = &[
+    "install",
+    "uninstall",
+    "clean",
+    "query",
+    "is_installed",
+    "list_installed",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub path: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path, self.line, self.message)
+    }
+}
+
+/// A YAML file being validated
+struct SourceFile {
+    path: String,
+    content: String,
+    positions: Positions,
+}
+
+impl SourceFile {
+    fn read(path: &Path) -> Result<SourceFile> {
+        return Ok(SourceFile::new(
+            path.to_string_lossy().to_string(),
+            std::fs::read_to_string(path)?,
+        ));
+    }
+
+    fn new(path: String, content: String) -> SourceFile {
+        let positions = Positions::parse(&content);
+        return SourceFile {
+            path,
+            content,
+            positions,
+        };
+    }
+
+    fn diagnostic(&self, line: usize, message: String) -> Diagnostic {
+        return Diagnostic {
+            path: self.path.clone(),
+            line,
+            message,
+        };
+    }
+
+    /// Line (1-based) of the node at the given path, like ["links", "0", "src"]
+    fn line(&self, path: &[&str]) -> usize {
+        return self.positions.line(path);
+    }
+
+    fn parse_error(&self, error: &anyhow::Error) -> Diagnostic {
+        let line = error
+            .downcast_ref::<serde_yaml::Error>()
+            .and_then(|error| error.location())
+            .map(|location| location.line())
+            .unwrap_or(1);
+        return self.diagnostic(line, error.to_string());
+    }
+}
+
+fn check_keys(
+    value: &Value,
+    known_keys: &[&str],
+    section: &str,
+    path: &[&str],
+    file: &SourceFile,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mapping = match value.as_mapping() {
+        Some(mapping) => mapping,
+        None => return,
+    };
+    for key in mapping.keys() {
+        let key = key.as_str().unwrap_or_default();
+        if !known_keys.contains(&key) {
+            diagnostics.push(file.diagnostic(
+                file.line(&[path, &[key]].concat()),
+                format!("Unknown key \"{}\" in {}", key, section),
+            ));
+        }
+    }
+}
+
+fn check_package_keys(value: &Value, file: &SourceFile, diagnostics: &mut Vec<Diagnostic>) {
+    check_keys(value, PACKAGE_KEYS, "package", &[], file, diagnostics);
+    for (index, dependency) in value["dependencies"]
+        .as_sequence()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        let path = ["dependencies", &index.to_string()];
+        check_keys(
+            dependency,
+            DEPENDENCY_KEYS,
+            "dependency",
+            &path,
+            file,
+            diagnostics,
+        );
+    }
+    for (index, link) in value["links"]
+        .as_sequence()
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        let path = ["links", &index.to_string()];
+        check_keys(link, LINK_KEYS, "link", &path, file, diagnostics);
+    }
+    for hook in SCRIPT_HOOKS.iter() {
+        check_keys(&value[*hook], SCRIPT_KEYS, hook, &[hook], file, diagnostics);
+    }
+}
+
+fn check_config_keys(value: &Value, file: &SourceFile, diagnostics: &mut Vec<Diagnostic>) {
+    check_keys(value, CONFIG_KEYS, "config", &[], file, diagnostics);
+    for (name, package_manager) in value["package_managers"].as_mapping().into_iter().flatten() {
+        let name = name.as_str().unwrap_or_default();
+        check_keys(
+            package_manager,
+            PACKAGE_MANAGER_KEYS,
+            "package manager",
+            &["package_managers", name],
+            file,
+            diagnostics,
+        );
+        check_keys(
+            &package_manager["commands"],
+            COMMANDS_KEYS,
+            "commands",
+            &["package_managers", name, "commands"],
+            file,
+            diagnostics,
+        );
+    }
+}
+
+/// Check the keys and commands of a config.yml, which may be a partial one
+/// completed by profiles, so it is deserialized only through its host
+fn validate_config(path: &Path, diagnostics: &mut Vec<Diagnostic>) -> Option<SourceFile> {
+    let file = match SourceFile::read(path) {
+        Ok(file) => file,
+        Err(error) => {
+            diagnostics.push(Diagnostic {
+                path: path.to_string_lossy().to_string(),
+                line: 1,
+                message: error.to_string(),
+            });
+            return None;
+        }
+    };
+    let value = match serde_yaml::from_str::<Value>(&file.content) {
+        Ok(value) => value,
+        Err(error) => {
+            diagnostics.push(file.parse_error(&error.into()));
+            return None;
+        }
+    };
+    check_config_keys(&value, &file, diagnostics);
+    for (source, package_manager) in value["package_managers"].as_mapping().into_iter().flatten() {
+        let source = source.as_str().unwrap_or_default();
+        for name in ["install", "uninstall"] {
+            let command = match package_manager["commands"][name].as_str() {
+                Some(command) => command,
+                None => continue,
+            };
+            if !command.contains("<package>") {
+                diagnostics.push(file.diagnostic(
+                    file.line(&["package_managers", source, "commands", name]),
+                    format!(
+                        "Command \"{}\" of {} does not contain <package>",
+                        command, source
+                    ),
+                ));
+            }
+        }
+    }
+    return Some(file);
+}
+
+fn validate_package(
+    path: &Path,
+    config: &Option<HostConfiguration>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<(PackageDefinition, SourceFile)> {
+    let file = match SourceFile::read(path) {
+        Ok(file) => file,
+        Err(error) => {
+            diagnostics.push(Diagnostic {
+                path: path.to_string_lossy().to_string(),
+                line: 1,
+                message: error.to_string(),
+            });
+            return None;
+        }
+    };
+    if let Ok(value) = serde_yaml::from_str::<Value>(&file.content) {
+        check_package_keys(&value, &file, diagnostics);
+    }
+    let package = match PackageDefinition::parse(path, &file.content) {
+        Ok(package) => package,
+        Err(error) => {
+            diagnostics.push(file.parse_error(&error));
+            return None;
+        }
+    };
+    if let Some(config) = config {
+        for (index, dependency) in package.dependencies.iter().enumerate() {
+            if dependency.source != "dot"
+                && !config.package_managers.contains_key(&dependency.source)
+            {
+                diagnostics.push(file.diagnostic(
+                    file.line(&["dependencies", &index.to_string()]),
+                    format!(
+                        "Package manager \"{}\" of {} is not declared in config.yml",
+                        dependency.source, dependency
+                    ),
+                ));
+            }
+        }
+    }
+    return Some((package, file));
+}
+
+fn validate_links(
+    package: &PackageDefinition,
+    file: &SourceFile,
+    context: &VariableContext,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (index, link) in package.links.iter().enumerate() {
+        let line = file.line(&["links", &index.to_string(), "src"]);
+        match context.expand(&package.path, &link.src) {
+            Ok(src) => {
+                if pathbuf![&package.path, &src].symlink_metadata().is_err() {
+                    diagnostics.push(
+                        file.diagnostic(line, format!("Link source \"{}\" does not exist", src)),
+                    );
+                }
+            }
+            Err(error) => diagnostics.push(file.diagnostic(line, format!("{:#}", error))),
+        }
+    }
+}
+
+/// Check a host configuration and every package it depends on
+pub fn validate_host(hostname: &str) -> Result<Vec<Diagnostic>> {
+    let mut diagnostics = vec![];
+    let host_path = pathbuf!["hosts", hostname];
+    let config_path = pathbuf![&host_path, "config.yml"];
+    let host_file = validate_config(&config_path, &mut diagnostics);
+
+    // Profiles are checked through the merged host
+    let mut config: Option<HostConfiguration> = None;
+    let mut merged_host = None;
+    if let Some(file) = &host_file {
+        let extends = serde_yaml::from_str::<Value>(&file.content)
+            .ok()
+            .and_then(|value| read_extends(&value).ok())
+            .unwrap_or_default();
+        if extends.is_empty() {
+            match serde_yaml::from_str(&file.content) {
+                Ok(host_config) => config = Some(host_config),
+                Err(error) => diagnostics.push(file.parse_error(&error.into())),
+            }
+        } else {
+            match HostDefinition::from_path(&host_path) {
+                Ok(definition) => {
+                    config = Some(definition.config.clone());
+                    merged_host = Some(definition);
+                }
+                Err(error) => {
+                    diagnostics
+                        .push(file.diagnostic(file.line(&["extends"]), format!("{:#}", error)));
+                    return Ok(diagnostics);
+                }
+            }
+        }
+    }
+
+    let mut host_vars: Variables = config
+        .as_ref()
+        .map(|config| config.vars.clone())
+        .unwrap_or_default();
+    let vars_path = pathbuf![&host_path, "vars.yml"];
+    if vars_path.exists() {
+        let file = SourceFile::read(&vars_path)?;
+        match serde_yaml::from_str::<Option<Variables>>(&file.content) {
+            Ok(vars) => host_vars.extend(vars.unwrap_or_default()),
+            Err(error) => diagnostics.push(file.parse_error(&error.into())),
+        }
+    }
+
+    let host = match validate_package(
+        &pathbuf![&host_path, "package.yml"],
+        &config,
+        &mut diagnostics,
+    ) {
+        Some(host) => host,
+        None => return Ok(diagnostics),
+    };
+    // Each profile is checked in its own files, then through the merged host
+    let mut packages: Vec<(PackageDefinition, SourceFile)> = vec![host];
+    let mut host_package = None;
+    if let Some(definition) = merged_host {
+        for name in profile_names(&definition.config.extends)?.iter() {
+            let config_path = pathbuf![PROFILES_DIRECTORY, name, "config.yml"];
+            if config_path.exists() {
+                validate_config(&config_path, &mut diagnostics);
+            }
+            let package_path = pathbuf![PROFILES_DIRECTORY, name, "package.yml"];
+            if package_path.exists() {
+                packages.extend(validate_package(&package_path, &config, &mut diagnostics));
+            }
+        }
+        host_package = Some(definition.package);
+        host_vars = definition.vars;
+    }
+    let layer_count = packages.len();
+
+    // Walk through the dot packages, each one validated once
+    let mut visited: Vec<String> = vec![];
+    let mut has_missing_package = false;
+    let mut index = 0;
+    while index < packages.len() {
+        let (package, file) = &packages[index];
+        let mut found = vec![];
+        for (dependency_index, dependency) in package.dependencies.iter().enumerate() {
+            if dependency.source != "dot" || visited.contains(&dependency.name) {
+                continue;
+            }
+            visited.push(dependency.name.clone());
+            let package_path = pathbuf!["packages", &dependency.name, "package.yml"];
+            if !package_path.exists() {
+                has_missing_package = true;
+                diagnostics.push(file.diagnostic(
+                    file.line(&["dependencies", &dependency_index.to_string()]),
+                    format!(
+                        "Package {} not found at {}",
+                        dependency,
+                        package_path.to_string_lossy()
+                    ),
+                ));
+                continue;
+            }
+            found.extend(validate_package(&package_path, &config, &mut diagnostics));
+        }
+        packages.append(&mut found);
+        index += 1;
+    }
+
+    let host_package = host_package.unwrap_or_else(|| packages[0].0.clone());
+    if !has_missing_package {
+        if let Err(error) = resolver::resolve_dependencies(&host_package) {
+            diagnostics.push(packages[0].1.diagnostic(1, format!("{:#}", error)));
+        }
+    }
+
+    let repo: PackageCollection = packages[layer_count..]
+        .iter()
+        .map(|(package, _)| (package.name.clone(), package.clone()))
+        .collect();
+    let context = VariableContext::new(gather_facts(hostname), host_vars, &host_package, &repo);
+    for (package, file) in packages.iter() {
+        validate_links(package, file, &context, &mut diagnostics);
+    }
+    return Ok(diagnostics);
+}
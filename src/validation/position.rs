@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// Container being walked through, with the path leading to it
+#[derive(Debug)]
+enum Frame {
+    /// Key waiting for its value, None while expecting a key
+    Mapping(Vec<String>, Option<String>),
+    /// Index of the next item
+    Sequence(Vec<String>, usize),
+}
+
+/// Line (1-based) of every node of a YAML document, by path from the root.
+/// A map entry is located at its key, a list item at its start.
+#[derive(Debug, Default)]
+pub struct Positions {
+    lines: HashMap<Vec<String>, usize>,
+    stack: Vec<Frame>,
+}
+
+impl MarkedEventReceiver for Positions {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let is_node = matches!(
+            event,
+            Event::Scalar(..) | Event::Alias(_) | Event::SequenceStart(_) | Event::MappingStart(_)
+        );
+        let mut path = vec![];
+        if is_node {
+            match self.stack.last_mut() {
+                Some(Frame::Mapping(parent, key @ None)) => {
+                    // A key, the value comes next
+                    let name = match &event {
+                        Event::Scalar(name, ..) => name.clone(),
+                        _ => "?".to_string(),
+                    };
+                    path = parent.clone();
+                    path.push(name.clone());
+                    self.lines.entry(path.clone()).or_insert(mark.line());
+                    *key = Some(name);
+                    if !matches!(event, Event::SequenceStart(_) | Event::MappingStart(_)) {
+                        return;
+                    }
+                }
+                Some(Frame::Mapping(parent, key)) => {
+                    path = parent.clone();
+                    path.push(key.take().unwrap());
+                }
+                Some(Frame::Sequence(parent, index)) => {
+                    path = parent.clone();
+                    path.push(index.to_string());
+                    self.lines.insert(path.clone(), mark.line());
+                    *index += 1;
+                }
+                None => {}
+            }
+        }
+        match event {
+            Event::MappingStart(_) => self.stack.push(Frame::Mapping(path, None)),
+            Event::SequenceStart(_) => self.stack.push(Frame::Sequence(path, 0)),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Positions {
+    /// Positions of a document, empty when it is not valid YAML
+    pub fn parse(content: &str) -> Positions {
+        let mut positions = Positions::default();
+        if Parser::new(content.chars())
+            .load(&mut positions, false)
+            .is_err()
+        {
+            return Positions::default();
+        }
+        positions.stack.clear();
+        return positions;
+    }
+
+    /// Line of the node at the given path, or of its closest located parent, 1 at worst
+    pub fn line(&self, path: &[&str]) -> usize {
+        let path: Vec<String> = path.iter().map(|segment| segment.to_string()).collect();
+        for length in (1..=path.len()).rev() {
+            if let Some(line) = self.lines.get(&path[..length]) {
+                return *line;
+            }
+        }
+        return 1;
+    }
+}
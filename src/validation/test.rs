@@ -0,0 +1,47 @@
+use super::*;
+
+#[test]
+fn test_unknown_keys_are_reported_with_their_line() {
+    let file = SourceFile::new(
+        "packages/zsh/package.yml".to_string(),
+        "description: zsh
+dependencies:
+  - zsh
+  - name: zsh-completions
+    optional: true
+links:
+  - src: zshrc
+    dest: ~/.zshrc
+    symlink: true
+  - src: zshenv
+    dest: ~/.zshenv
+    symlink: true
+  - zshrc:~/.config/zsh/.zshrc
+post_install:
+  run: chsh -s /bin/zsh
+  once: true
+"
+        .to_string(),
+    );
+    let value: Value = serde_yaml::from_str(&file.content).unwrap();
+    let mut diagnostics = vec![];
+    check_package_keys(&value, &file, &mut diagnostics);
+    assert_eq!(
+        vec![
+            "packages/zsh/package.yml:5: Unknown key \"optional\" in dependency",
+            "packages/zsh/package.yml:9: Unknown key \"symlink\" in link",
+            "packages/zsh/package.yml:12: Unknown key \"symlink\" in link",
+            "packages/zsh/package.yml:16: Unknown key \"once\" in post_install",
+        ],
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect::<Vec<String>>()
+    );
+    assert_eq!(3, file.line(&["dependencies", "0"]));
+    assert_eq!(4, file.line(&["dependencies", "1"]));
+    assert_eq!(10, file.line(&["links", "1", "src"]));
+    // A link written as a string is located at its item
+    assert_eq!(13, file.line(&["links", "2", "src"]));
+    assert_eq!(1, file.line(&["vars"]));
+}
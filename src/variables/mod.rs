@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::package::{PackageCollection, PackageDefinition};
+
+#[cfg(test)]
+mod test;
+
+pub type Variables = HashMap<String, String>;
+
+/// Variables available while planning and applying a host.
+///
+/// From the lowest to the highest precedence: package `vars:` defaults,
+/// host facts, `config.yml` `vars:` and `hosts/<hostname>/vars.yml`.
+#[derive(Debug, Default)]
+pub struct VariableContext {
+    facts: Variables,
+    host_vars: Variables,
+    package_vars: HashMap<String, Variables>,
+}
+
+impl VariableContext {
+    pub fn new(
+        facts: Variables,
+        host_vars: Variables,
+        host_package: &PackageDefinition,
+        repo: &PackageCollection,
+    ) -> VariableContext {
+        let mut context = VariableContext {
+            facts,
+            host_vars,
+            package_vars: HashMap::new(),
+        };
+        context
+            .package_vars
+            .insert(host_package.path.clone(), host_package.vars.clone());
+        for package in repo.values() {
+            context
+                .package_vars
+                .insert(package.path.clone(), package.vars.clone());
+        }
+        return context;
+    }
+
+    /// All variables visible from the package at the given path
+    pub fn for_package(&self, package_path: &str) -> Variables {
+        let mut variables = self
+            .package_vars
+            .get(package_path)
+            .cloned()
+            .unwrap_or_default();
+        variables.extend(self.facts.clone());
+        variables.extend(self.host_vars.clone());
+        return variables;
+    }
+
+    /// Declared variables (without facts) exported to the package scripts
+    pub fn exported(&self, package_path: &str) -> BTreeMap<String, String> {
+        let mut variables = BTreeMap::new();
+        if let Some(package_vars) = self.package_vars.get(package_path) {
+            variables.extend(package_vars.clone());
+        }
+        variables.extend(self.host_vars.clone());
+        variables.retain(|name, _| is_env_name(name));
+        return variables;
+    }
+
+    /// Expand `~`, `$NAME` and `${NAME}` in a path,
+    /// variables are looked up before the environment
+    pub fn expand(&self, package_path: &str, value: &str) -> Result<String> {
+        let variables = self.for_package(package_path);
+        let expanded = shellexpand::full_with_context(
+            value,
+            || std::env::var("HOME").ok(),
+            |name: &str| -> Result<Option<String>, std::env::VarError> {
+                if let Some(value) = variables.get(name) {
+                    return Ok(Some(value.clone()));
+                }
+                return std::env::var(name).map(Some);
+            },
+        )
+        .context(format!(
+            "Unable to expand \"{}\" in {}",
+            value, package_path
+        ))?;
+        return Ok(expanded.to_string());
+    }
+}
+
+fn is_env_name(name: &str) -> bool {
+    return !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
@@ -0,0 +1,56 @@
+use crate::{hashmap, host::HostDefinition, package::PackageCollection};
+
+use super::*;
+
+#[test]
+fn test_variable_precedence() {
+    let host_path = std::env::temp_dir().join(format!("dotstrap-test-vars-{}", std::process::id()));
+    std::fs::create_dir_all(&host_path).unwrap();
+    std::fs::write(
+        host_path.join("config.yml"),
+        "vars:\n  email: config@example.com\n  editor: vim\n  user: config\n",
+    )
+    .unwrap();
+    std::fs::write(host_path.join("vars.yml"), "email: vars@example.com\n").unwrap();
+    std::fs::write(host_path.join("package.yml"), "dependencies: []\n").unwrap();
+    let host_definition = HostDefinition::from_path(&host_path).unwrap();
+    std::fs::remove_dir_all(&host_path).unwrap();
+
+    let mut git: PackageDefinition = serde_yaml::from_str(
+        "vars:\n  email: git@example.com\n  os: package\n  dir: .config/git\n",
+    )
+    .unwrap();
+    git.path = "packages/git".to_string();
+    let repo: PackageCollection = hashmap!["git".to_string() => git];
+    let facts = hashmap![
+        "os".to_string() => "linux".to_string(),
+        "user".to_string() => "fact".to_string()
+    ];
+    let context =
+        VariableContext::new(facts, host_definition.vars, &host_definition.package, &repo);
+
+    let variables = context.for_package("packages/git");
+    // package vars < facts < config.yml vars < vars.yml
+    assert_eq!("linux", variables["os"]);
+    assert_eq!("config", variables["user"]);
+    assert_eq!("vars@example.com", variables["email"]);
+    assert_eq!(".config/git", variables["dir"]);
+    assert_eq!(None, context.for_package("hosts/test").get("dir"));
+
+    std::env::set_var("DOTSTRAP_TEST_EDITOR", "from-env");
+    assert_eq!(
+        format!("{}/.config/git/vim-linux", std::env::var("HOME").unwrap()),
+        context
+            .expand("packages/git", "~/$dir/${editor}-$os")
+            .unwrap()
+    );
+    assert_eq!(
+        "from-env",
+        context
+            .expand("packages/git", "$DOTSTRAP_TEST_EDITOR")
+            .unwrap()
+    );
+    assert!(context
+        .expand("packages/git", "$DOTSTRAP_TEST_UNDEFINED")
+        .is_err());
+}